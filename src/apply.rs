@@ -0,0 +1,537 @@
+use crate::bootloader;
+use crate::cpio::{self, Entry};
+use crate::iasl;
+use crate::journal;
+use crate::table::{self, TableHeader};
+use crate::workspace;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Local;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A modified table that passed validation and is ready to be installed.
+pub struct ValidatedTable {
+    pub dsl_file: String,
+    /// Source the AML was compiled from, as read at validation.
+    pub source: String,
+    /// Copy of `source` in the staging directory.
+    pub snapshot: PathBuf,
+    pub aml: PathBuf,
+    pub header: TableHeader,
+    pub size: usize,
+    pub origin_oem_revision: Option<u32>,
+}
+
+/// Outcome of the pre-apply validation stage.
+pub struct Validation {
+    pub tables: Vec<ValidatedTable>,
+}
+
+impl Validation {
+    /// Human readable description of the validated tables.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for t in self.tables.iter() {
+            let revision = match t.origin_oem_revision {
+                Some(origin) if origin != t.header.oem_revision => {
+                    format!("oem rev {:#x} -> {:#x}", origin, t.header.oem_revision)
+                }
+                Some(_) => format!("oem rev {:#x} (unchanged)", t.header.oem_revision),
+                None => format!("oem rev {:#x}", t.header.oem_revision),
+            };
+            lines.push(format!(
+                "{:<12} {:<4} {:>8} bytes  {}",
+                t.dsl_file, t.header.signature, t.size, revision
+            ));
+        }
+        lines
+    }
+}
+
+/// Table name of a dsl file, used to name its compiled AML.
+pub fn stem(dsl_file: &str) -> &str {
+    dsl_file.strip_suffix(".dsl").unwrap_or(dsl_file)
+}
+
+/// Compile a modified table, disassemble the result and compile it once more
+/// to make sure the AML we are about to ship is well-formed. The source is
+/// compiled from a copy, so later edits do not change what was validated.
+fn validate_table(
+    dsl_file: &str,
+    origin_dir: &Path,
+    modified_dir: &Path,
+    staging_dir: &Path,
+) -> Result<ValidatedTable> {
+    let stem = stem(dsl_file);
+    let path = modified_dir.join(dsl_file);
+    let source =
+        fs::read_to_string(&path).with_context(|| format!("fail to read {}", path.display()))?;
+    let snapshot = staging_dir.join(format!("{}_source.dsl", stem));
+    fs::write(&snapshot, &source)?;
+    let prefix = staging_dir.join(stem);
+    let check_prefix = staging_dir.join(format!("{}_check", stem));
+
+    let aml = iasl::compile(&snapshot, &prefix, dsl_file)?;
+    let data = fs::read(&aml).with_context(|| format!("{}: no AML produced", dsl_file))?;
+    let header = table::verify(&data).with_context(|| format!("{}: bad AML", dsl_file))?;
+
+    let what = format!("{} (recheck)", dsl_file);
+    let check_source = iasl::disassemble(&aml, &check_prefix, &what)?;
+    let check_aml = iasl::compile(&check_source, &check_prefix, &what)?;
+    let check = fs::read(check_aml)
+        .with_context(|| format!("{}: no AML produced on recompile", dsl_file))?;
+    let check =
+        table::verify(&check).with_context(|| format!("{}: bad AML on recompile", dsl_file))?;
+    if check.signature != header.signature
+        || check.oem_id != header.oem_id
+        || check.oem_table_id != header.oem_table_id
+        || check.oem_revision != header.oem_revision
+    {
+        bail!("{}: header changed across disassembly", dsl_file);
+    }
+
+    let origin_oem_revision = TableHeader::read(&origin_dir.join(format!("{}.dat", stem)))
+        .ok()
+        .map(|h| h.oem_revision);
+
+    Ok(ValidatedTable {
+        dsl_file: dsl_file.to_owned(),
+        source,
+        snapshot,
+        aml,
+        size: data.len(),
+        header,
+        origin_oem_revision,
+    })
+}
+
+/// Validate all modified tables before anything under /boot is touched.
+pub fn validate(
+    dsl_files: &[String],
+    origin_dir: &Path,
+    modified_dir: &Path,
+    staging_dir: &Path,
+    progress: &dyn Fn(&str),
+    cancel: &AtomicBool,
+) -> Result<Validation> {
+    if staging_dir.exists() {
+        fs::remove_dir_all(staging_dir)?;
+    }
+    fs::create_dir_all(staging_dir)?;
+
+    let mut tables = vec![];
+    for dsl_file in dsl_files.iter() {
+        if cancel.load(Ordering::SeqCst) {
+            bail!("validation cancelled");
+        }
+        progress(&format!("validating {}", dsl_file));
+        tables.push(validate_table(
+            dsl_file,
+            origin_dir,
+            modified_dir,
+            staging_dir,
+        )?);
+    }
+
+    Ok(Validation { tables })
+}
+
+/// Validate tables of the workspace, compiled in its staging directory.
+pub fn validate_workspace(
+    dsl_files: &[String],
+    progress: &dyn Fn(&str),
+    cancel: &AtomicBool,
+) -> Result<Validation> {
+    validate(
+        dsl_files,
+        &workspace::origin_dir(),
+        &workspace::modified_dir(),
+        &workspace::staging_dir(),
+        progress,
+        cancel,
+    )
+}
+
+/// Directory in the initrd the kernel looks for upgraded tables.
+const ACPI_OVERRIDE_DIR: &str = "kernel/firmware/acpi";
+
+/// Steps of an apply transaction, in order.
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    BuildArchive,
+    Sync,
+    Verify,
+    Install,
+    UpdateBootEntry,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Step::BuildArchive => "build initrd",
+            Step::Sync => "sync initrd",
+            Step::Verify => "verify initrd",
+            Step::Install => "install initrd",
+            Step::UpdateBootEntry => "update boot entry",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Everything an apply is going to do, computed without side effects.
+pub struct Plan {
+    pub boot_entry: String,
+    pub validation: Validation,
+    pub archive: Vec<u8>,
+    pub previous_initrd: PathBuf,
+    pub initrd: PathBuf,
+    pub commands: Vec<String>,
+}
+
+impl Plan {
+    /// Human readable description of the plan.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![format!("boot entry: {}", self.boot_entry)];
+        lines.push("tables:".to_string());
+        for line in self.validation.summary() {
+            lines.push(format!("  {}", line));
+        }
+        lines.push(format!("archive ({} bytes):", self.archive.len()));
+        for entry in cpio::parse(&self.archive).unwrap_or_default() {
+            match entry.data {
+                Some(data) => lines.push(format!("  {} ({} bytes)", entry.name, data.len())),
+                None => lines.push(format!("  {}/", entry.name)),
+            }
+        }
+        lines.push(format!(
+            "original initrd: {}",
+            self.previous_initrd.display()
+        ));
+        lines.push(format!("new initrd: {}", self.initrd.display()));
+        lines.push("bootloader commands:".to_string());
+        for command in self.commands.iter() {
+            lines.push(format!("  {}", command));
+        }
+        lines
+    }
+}
+
+/// Work out what installing the validated tables would do.
+pub fn plan(validation: Validation, boot_dir: &Path) -> Result<Plan> {
+    if workspace::is_offline() {
+        bail!("tables imported from a dump can only be exported");
+    }
+    let boot_entry = bootloader::default_kernel()?;
+    let previous_initrd = bootloader::initrd(&boot_entry)?;
+    let timestamp = Local::now().format("%Y%m%d%H%M%S");
+    let initrd = boot_dir.join(format!("instrumented_initrd_{}", timestamp));
+    let commands = vec![bootloader::set_initrd_command(&boot_entry, &initrd)?];
+    Ok(Plan {
+        boot_entry,
+        archive: override_archive(&validation)?,
+        validation,
+        previous_initrd,
+        initrd,
+        commands,
+    })
+}
+
+/// Result of a successful apply.
+pub struct Installed {
+    pub boot_entry: String,
+    pub initrd: PathBuf,
+    pub previous_initrd: PathBuf,
+}
+
+/// Side effects that have to be undone when a later step fails.
+enum Undo {
+    RemoveFile(PathBuf),
+    RestoreInitrd { kernel: String, initrd: PathBuf },
+}
+
+struct Transaction<'a> {
+    undo: Vec<Undo>,
+    progress: &'a dyn Fn(&str),
+    cancel: &'a AtomicBool,
+}
+
+impl Transaction<'_> {
+    fn rollback(&mut self) -> Vec<String> {
+        let mut errors = vec![];
+        while let Some(undo) = self.undo.pop() {
+            let result = match &undo {
+                Undo::RemoveFile(path) => fs::remove_file(path).map_err(anyhow::Error::from),
+                Undo::RestoreInitrd { kernel, initrd } => bootloader::set_initrd(kernel, initrd),
+            };
+            if let Err(e) = result {
+                errors.push(format!("{:#}", e));
+            }
+        }
+        errors
+    }
+}
+
+/// Archive holding the validated tables, to be prepended to an initrd.
+pub fn override_archive(validation: &Validation) -> Result<Vec<u8>> {
+    let mut entries = vec![];
+    let mut dir = String::new();
+    for component in ACPI_OVERRIDE_DIR.split('/') {
+        if !dir.is_empty() {
+            dir.push('/');
+        }
+        dir.push_str(component);
+        entries.push(Entry {
+            name: dir.clone(),
+            data: None,
+        });
+    }
+    for t in validation.tables.iter() {
+        entries.push(Entry {
+            name: format!("{}/{}.aml", ACPI_OVERRIDE_DIR, stem(&t.dsl_file)),
+            data: Some(fs::read(&t.aml)?),
+        });
+    }
+    Ok(cpio::build(&entries))
+}
+
+/// Write the new initrd to `path`, removing what was written if it fails.
+fn build_initrd(archive: &[u8], origin_initrd: &Path, path: &Path) -> Result<File> {
+    let mut file = File::create(path)?;
+    let written = file
+        .write_all(archive)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| {
+            let mut origin = File::open(origin_initrd)
+                .with_context(|| format!("fail to open {}", origin_initrd.display()))?;
+            std::io::copy(&mut origin, &mut file)?;
+            Ok(())
+        });
+    if let Err(e) = written {
+        let _ = fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(file)
+}
+
+fn same_content(a: &mut impl Read, b: &mut impl Read) -> Result<bool> {
+    let mut buf_a = vec![0u8; 1 << 16];
+    let mut buf_b = vec![0u8; 1 << 16];
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+fn verify_initrd(archive: &[u8], origin_initrd: &Path, path: &Path) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut head = vec![0u8; archive.len()];
+    file.read_exact(&mut head)?;
+    let expected = cpio::parse(archive)?;
+    let written = cpio::parse(&head)?;
+    if expected.len() != written.len()
+        || expected
+            .iter()
+            .zip(written.iter())
+            .any(|(a, b)| a.name != b.name || a.data != b.data)
+    {
+        bail!("override archive does not match");
+    }
+    let mut origin = File::open(origin_initrd)?;
+    if !same_content(&mut file, &mut origin)? {
+        bail!("original initrd was not copied intact");
+    }
+    Ok(())
+}
+
+fn run_step<T>(
+    transaction: &mut Transaction,
+    step: Step,
+    f: impl FnOnce(&mut Transaction) -> Result<T>,
+) -> Result<T> {
+    // the last chance to cancel is right before the bootloader is touched
+    let result = if transaction.cancel.load(Ordering::SeqCst) {
+        Err(anyhow!("cancelled"))
+    } else {
+        (transaction.progress)(&format!("{}", step));
+        f(transaction)
+    };
+    result.map_err(|e| {
+        let errors = transaction.rollback();
+        let e = e.context(format!("{} failed", step));
+        if errors.is_empty() {
+            e.context("changes rolled back")
+        } else {
+            e.context(format!("rollback incomplete: {}", errors.join("; ")))
+        }
+    })
+}
+
+/// Carry out a plan as a transaction: the new initrd is written to a
+/// temporary file, synced, verified and renamed into place before the boot
+/// entry is updated. Completed steps are undone if a later one fails or the
+/// apply is cancelled before the boot entry is updated.
+pub fn install(plan: &Plan, progress: &dyn Fn(&str), cancel: &AtomicBool) -> Result<Installed> {
+    let kernel = plan.boot_entry.clone();
+    let previous_initrd = plan.previous_initrd.clone();
+    let initrd = plan.initrd.clone();
+    let boot_dir = initrd
+        .parent()
+        .ok_or_else(|| anyhow!("invalid initrd path {}", initrd.display()))?;
+    let temp = boot_dir.join(format!(
+        ".{}.tmp",
+        initrd.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let archive = &plan.archive;
+    let mut transaction = Transaction {
+        undo: vec![],
+        progress,
+        cancel,
+    };
+
+    let file = run_step(&mut transaction, Step::BuildArchive, |t| {
+        let file = build_initrd(archive, &previous_initrd, &temp)?;
+        t.undo.push(Undo::RemoveFile(temp.clone()));
+        Ok(file)
+    })?;
+    run_step(&mut transaction, Step::Sync, |_| Ok(file.sync_all()?))?;
+    run_step(&mut transaction, Step::Verify, |_| {
+        verify_initrd(archive, &previous_initrd, &temp)
+    })?;
+    run_step(&mut transaction, Step::Install, |t| {
+        fs::rename(&temp, &initrd)?;
+        t.undo.pop();
+        t.undo.push(Undo::RemoveFile(initrd.clone()));
+        File::open(boot_dir)?.sync_all()?;
+        Ok(())
+    })?;
+    run_step(&mut transaction, Step::UpdateBootEntry, |t| {
+        t.undo.push(Undo::RestoreInitrd {
+            kernel: kernel.clone(),
+            initrd: previous_initrd.clone(),
+        });
+        bootloader::set_initrd(&kernel, &initrd)?;
+        if bootloader::initrd(&kernel)? != initrd {
+            bail!("boot entry still points to another initrd");
+        }
+        Ok(())
+    })?;
+
+    if let Err(e) = workspace::record_applied(&plan.validation) {
+        progress(&format!("fail to record the applied tables: {:#}", e));
+    }
+    let entry = journal::Entry::new("initrd", &kernel, &plan.validation).map(|mut entry| {
+        entry.initrd = Some(initrd.clone());
+        entry.previous_initrd = Some(previous_initrd.clone());
+        entry
+    });
+    if let Err(e) = entry.and_then(journal::record) {
+        progress(&format!("fail to record the apply in the journal: {:#}", e));
+    }
+    Ok(Installed {
+        boot_entry: kernel,
+        initrd,
+        previous_initrd,
+    })
+}
+
+/// Apply the modified tables of the workspace from the command line. With
+/// `dry_run` only the plan is printed.
+pub fn run(dry_run: bool) -> Result<()> {
+    if workspace::is_offline() {
+        bail!("tables imported from a dump can only be exported, see `acpied export`");
+    }
+    let modified = workspace::modified()?;
+    let dsl_files = workspace::to_apply(&modified);
+    for dsl_file in modified.iter().filter(|f| !dsl_files.contains(f)) {
+        println!("{} excluded from apply", dsl_file);
+    }
+    if dsl_files.is_empty() {
+        println!("no modified tables to apply");
+        return Ok(());
+    }
+
+    let progress = |line: &str| println!("{}", line);
+    let cancel = AtomicBool::new(false);
+    let validation = validate_workspace(&dsl_files, &progress, &cancel)?;
+    let plan = plan(validation, &bootloader::boot_dir())?;
+    for line in plan.describe() {
+        println!("{}", line);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let installed = install(&plan, &progress, &cancel)?;
+    println!(
+        "successfully set initrd for {}: {}",
+        installed.boot_entry,
+        installed.initrd.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Config};
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn rollback_restores_initrd() {
+        let root = env::temp_dir().join(format!("acpied-apply-{}", process::id()));
+        let entries = root.join("boot/loader/entries");
+        fs::create_dir_all(&entries).unwrap();
+        let entry = entries.join("abc-6.1.conf");
+        fs::write(&entry, "linux /vmlinuz-6.1\ninitrd /initramfs-6.1.img\n").unwrap();
+        let previous = root.join("boot/initramfs-6.1.img");
+        fs::write(&previous, "initrd").unwrap();
+        let initrd = root.join("boot/initramfs-6.1-acpied.img");
+        config::init(Config {
+            root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cancel = AtomicBool::new(false);
+        let mut transaction = Transaction {
+            undo: vec![],
+            progress: &|_| {},
+            cancel: &cancel,
+        };
+        run_step(&mut transaction, Step::Install, |t| {
+            fs::write(&initrd, "override")?;
+            t.undo.push(Undo::RemoveFile(initrd.clone()));
+            Ok(())
+        })
+        .unwrap();
+        let result: Result<()> = run_step(&mut transaction, Step::UpdateBootEntry, |t| {
+            t.undo.push(Undo::RestoreInitrd {
+                kernel: "/vmlinuz-6.1".to_string(),
+                initrd: previous.clone(),
+            });
+            bootloader::set_initrd("/vmlinuz-6.1", &initrd)?;
+            assert_eq!(bootloader::initrd("/vmlinuz-6.1")?, initrd);
+            bail!("forced failure")
+        });
+
+        let e = result.unwrap_err();
+        assert_eq!(format!("{}", e), "changes rolled back");
+        assert!(!initrd.exists());
+        assert_eq!(bootloader::initrd("/vmlinuz-6.1").unwrap(), previous);
+        assert_eq!(
+            fs::read_to_string(&entry).unwrap(),
+            "linux /vmlinuz-6.1\ninitrd /initramfs-6.1.img\n"
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,574 @@
+use crate::config;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BLS_ENTRIES: &str = "/boot/loader/entries";
+const GRUBENV: &str = "/boot/grub2/grubenv";
+
+/// Directory new initrd files are written to.
+pub fn boot_dir() -> PathBuf {
+    let config = config::get();
+    config.system_path(&config.boot_dir)
+}
+
+fn grubby(args: &[&str]) -> Result<String> {
+    let output = Command::new(&config::get().tools.grubby)
+        .args(args)
+        .output()
+        .context("fail to execute grubby")?;
+    if !output.status.success() {
+        bail!(
+            "grubby {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// A Boot Loader Specification entry, edited in place when working on a tree
+/// mounted somewhere else, where grubby cannot be used.
+struct BlsEntry {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+impl BlsEntry {
+    fn read(path: PathBuf) -> Result<Self> {
+        let text = fs::read_to_string(&path)?;
+        Ok(Self {
+            path,
+            lines: text.lines().map(String::from).collect(),
+        })
+    }
+
+    fn id(&self) -> String {
+        self.path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| {
+            let (k, v) = line.trim().split_once(char::is_whitespace)?;
+            (k == key).then(|| v.trim())
+        })
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{} {}", key, value);
+        match self
+            .lines
+            .iter()
+            .position(|l| l.split_whitespace().next() == Some(key))
+        {
+            Some(index) => self.lines[index] = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let temp = self.path.with_extension("conf.tmp");
+        fs::write(&temp, self.lines.join("\n") + "\n")?;
+        fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+
+    /// Main initrd of the entry; a microcode image may come first.
+    fn initrd(&self) -> Option<&str> {
+        let images: Vec<&str> = self.get("initrd")?.split_whitespace().collect();
+        main_initrd(&images).map(|i| images[i])
+    }
+
+    fn set_initrd(&mut self, initrd: &str) {
+        let images: Vec<String> = self
+            .get("initrd")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        let images = replace_initrd(images, Path::new(initrd));
+        self.set("initrd", &images.join(" "));
+    }
+}
+
+/// Compare entry names the way rpm compares versions, as grubby sorts the
+/// entries: runs of digits numerically, so `6.10` comes after `6.9`.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    fn segments(s: &str) -> Vec<&str> {
+        let mut segments = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+            rest = &rest[start..];
+            let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != digits)
+                .unwrap_or(rest.len());
+            segments.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        segments
+    }
+    let (a, b) = (segments(a), segments(b));
+    for (x, y) in a.iter().zip(b.iter()) {
+        let numeric = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+        let order = match (numeric(x), numeric(y)) {
+            (true, true) => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then(x.cmp(y))
+            }
+            // a number is newer than letters, as in rpm
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => x.cmp(y),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn bls_entries() -> Result<Vec<BlsEntry>> {
+    let dir = config::get().system_path(BLS_ENTRIES);
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .with_context(|| format!("fail to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "conf"))
+        .collect();
+    paths.sort_by(|a, b| version_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    paths.into_iter().map(BlsEntry::read).collect()
+}
+
+/// Entry saved as default in grubenv, or the newest one by version.
+fn bls_default_entry() -> Result<BlsEntry> {
+    let saved = fs::read_to_string(config::get().system_path(GRUBENV))
+        .ok()
+        .and_then(|env| {
+            env.lines()
+                .find_map(|l| l.strip_prefix("saved_entry=").map(String::from))
+        });
+    let mut entries = bls_entries()?;
+    if let Some(saved) = saved {
+        if let Some(index) = entries.iter().position(|e| e.id() == saved) {
+            return Ok(entries.swap_remove(index));
+        }
+    }
+    entries
+        .pop()
+        .ok_or_else(|| anyhow!("no boot entries found"))
+}
+
+fn bls_entry(kernel: &str) -> Result<BlsEntry> {
+    bls_entries()?
+        .into_iter()
+        .find(|e| e.get("linux") == Some(kernel))
+        .ok_or_else(|| anyhow!("no boot entry for {}", kernel))
+}
+
+/// Paths in entries are relative to the partition holding them, which is
+/// either /boot or the root file system.
+fn bls_resolve(path: &str) -> PathBuf {
+    let config = config::get();
+    let on_boot = config
+        .system_path("/boot")
+        .join(path.trim_start_matches('/'));
+    if on_boot.exists() {
+        on_boot
+    } else {
+        config.system_path(path)
+    }
+}
+
+fn bls_relative(entry: &BlsEntry, path: &Path) -> Result<String> {
+    let config = config::get();
+    let base = match entry.initrd() {
+        Some(initrd) if !initrd.starts_with("/boot/") => config.system_path("/boot"),
+        _ => config.root.clone(),
+    };
+    let relative = path
+        .strip_prefix(&base)
+        .with_context(|| format!("{} is not under {}", path.display(), base.display()))?;
+    Ok(format!("/{}", relative.display()))
+}
+
+/// Kernel of the boot entry the bootloader will use on next boot.
+pub fn default_kernel() -> Result<String> {
+    if !config::get().is_live() {
+        let entry = bls_default_entry()?;
+        return entry
+            .get("linux")
+            .map(String::from)
+            .ok_or_else(|| anyhow!("{} has no kernel", entry.path.display()));
+    }
+    Ok(grubby(&["--default-kernel"])?.trim().to_string())
+}
+
+/// Initrd currently configured for the boot entry of `kernel`.
+pub fn initrd(kernel: &str) -> Result<PathBuf> {
+    if !config::get().is_live() {
+        let entry = bls_entry(kernel)?;
+        return entry
+            .initrd()
+            .map(bls_resolve)
+            .ok_or_else(|| anyhow!("no initrd configured for {}", kernel));
+    }
+    let images = grubby_initrds(kernel)?;
+    match main_initrd(&images) {
+        Some(i) => Ok(PathBuf::from(&images[i])),
+        None => bail!("no initrd configured for {}", kernel),
+    }
+}
+
+/// Index of the main initrd: the last image that is not a grub variable such
+/// as `$tuned_initrd`.
+fn main_initrd<S: AsRef<str>>(images: &[S]) -> Option<usize> {
+    images
+        .iter()
+        .rposition(|image| !image.as_ref().starts_with('$'))
+}
+
+/// Images of `initrd=` in `grubby --info` output, in boot order.
+fn initrd_images(info: &str) -> Vec<String> {
+    info.lines()
+        .find_map(|line| line.strip_prefix("initrd="))
+        .map(|value| {
+            value
+                .trim_matches('"')
+                .split_whitespace()
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn grubby_initrds(kernel: &str) -> Result<Vec<String>> {
+    Ok(initrd_images(&grubby(&[&format!("--info={}", kernel)])?))
+}
+
+/// Images with the main initrd replaced by `initrd`, others such as microcode
+/// or `$tuned_initrd` kept. Without a main initrd, it goes before the
+/// variables.
+fn replace_initrd(mut images: Vec<String>, initrd: &Path) -> Vec<String> {
+    let initrd = initrd.display().to_string();
+    match main_initrd(&images) {
+        Some(i) => images[i] = initrd,
+        None => {
+            let i = images
+                .iter()
+                .position(|image| image.starts_with('$'))
+                .unwrap_or(images.len());
+            images.insert(i, initrd);
+        }
+    }
+    images
+}
+
+fn set_initrd_args(kernel: &str, initrd: &Path) -> Result<Vec<String>> {
+    let images = replace_initrd(grubby_initrds(kernel)?, initrd);
+    Ok(vec![
+        format!("--update-kernel={}", kernel),
+        format!("--initrd={}", images.join(" ")),
+    ])
+}
+
+/// Command that points the boot entry of `kernel` at a different initrd.
+pub fn set_initrd_command(kernel: &str, initrd: &Path) -> Result<String> {
+    if !config::get().is_live() {
+        let entry = bls_entry(kernel)?;
+        return Ok(format!(
+            "set initrd {} in {}",
+            bls_relative(&entry, initrd)?,
+            entry.path.display()
+        ));
+    }
+    let args: Vec<String> = set_initrd_args(kernel, initrd)?
+        .into_iter()
+        .map(|a| match a.split_once('=') {
+            Some((flag, value)) if value.contains(' ') => format!("{}='{}'", flag, value),
+            _ => a,
+        })
+        .collect();
+    Ok(format!("grubby {}", args.join(" ")))
+}
+
+/// Point the boot entry of `kernel` at a different initrd.
+pub fn set_initrd(kernel: &str, initrd: &Path) -> Result<()> {
+    if !config::get().is_live() {
+        let mut entry = bls_entry(kernel)?;
+        let initrd = bls_relative(&entry, initrd)?;
+        entry.set_initrd(&initrd);
+        return entry.save();
+    }
+    let args = set_initrd_args(kernel, initrd)?;
+    grubby(&args.iter().map(|a| a.as_str()).collect::<Vec<&str>>())?;
+    Ok(())
+}
+
+/// Key of a kernel command line argument, `acpi_osi` for `acpi_osi=Linux`.
+pub fn arg_key(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(key, _)| key)
+}
+
+/// Split a kernel command line, keeping quoted values such as
+/// `acpi_osi="Windows 2015"` in one argument.
+pub fn split_args(cmdline: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    args
+}
+
+/// Kernel command line of the boot entry of `kernel`.
+pub fn kernel_args(kernel: &str) -> Result<Vec<String>> {
+    if !config::get().is_live() {
+        let entry = bls_entry(kernel)?;
+        return Ok(split_args(entry.get("options").unwrap_or_default()));
+    }
+    let info = grubby(&[&format!("--info={}", kernel)])?;
+    let args = info
+        .lines()
+        .find_map(|line| line.strip_prefix("args="))
+        .unwrap_or_default();
+    let args = args
+        .strip_prefix('"')
+        .and_then(|a| a.strip_suffix('"'))
+        .unwrap_or(args);
+    Ok(split_args(args))
+}
+
+/// Keys to drop before `set` is added, so each of its keys only keeps the
+/// new values.
+fn removed_keys(set: &[String], remove: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = vec![];
+    for key in set.iter().chain(remove.iter()).map(|a| arg_key(a)) {
+        if !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+        }
+    }
+    keys
+}
+
+fn update_args_args(kernel: &str, set: &[String], remove: &[String]) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    let keys = removed_keys(set, remove);
+    if !keys.is_empty() {
+        commands.push(vec![
+            format!("--update-kernel={}", kernel),
+            format!("--remove-args={}", keys.join(" ")),
+        ]);
+    }
+    if !set.is_empty() {
+        commands.push(vec![
+            format!("--update-kernel={}", kernel),
+            format!("--args={}", set.join(" ")),
+        ]);
+    }
+    commands
+}
+
+/// Commands that replace the arguments with the keys of `set` by `set` and
+/// drop the arguments keyed `remove` on the command line of `kernel`.
+pub fn update_args_commands(
+    kernel: &str,
+    set: &[String],
+    remove: &[String],
+) -> Result<Vec<String>> {
+    if !config::get().is_live() {
+        let entry = bls_entry(kernel)?;
+        let mut changes: Vec<String> = set.iter().map(|a| format!("set {}", a)).collect();
+        changes.extend(remove.iter().map(|r| format!("remove {}", r)));
+        return Ok(vec![format!(
+            "{} in {}",
+            changes.join(", "),
+            entry.path.display()
+        )]);
+    }
+    Ok(update_args_args(kernel, set, remove)
+        .into_iter()
+        .map(|args| {
+            let args: Vec<String> = args
+                .into_iter()
+                .map(|a| match a.split_once('=') {
+                    Some((flag, value)) if value.contains([' ', '"']) => {
+                        format!("{}='{}'", flag, value)
+                    }
+                    _ => a,
+                })
+                .collect();
+            format!("grubby {}", args.join(" "))
+        })
+        .collect())
+}
+
+/// Replace the arguments with the keys of `set` by `set` and drop the
+/// arguments keyed `remove` on the command line of `kernel`.
+pub fn update_args(kernel: &str, set: &[String], remove: &[String]) -> Result<()> {
+    if !config::get().is_live() {
+        let mut entry = bls_entry(kernel)?;
+        let keys = removed_keys(set, remove);
+        let mut args = split_args(entry.get("options").unwrap_or_default());
+        args.retain(|a| !keys.iter().any(|k| k == arg_key(a)));
+        args.extend(set.iter().cloned());
+        entry.set("options", &args.join(" "));
+        return entry.save();
+    }
+    for args in update_args_args(kernel, set, remove) {
+        grubby(&args.iter().map(|a| a.as_str()).collect::<Vec<&str>>())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn grubby_initrd_images() {
+        let info = "index=0\nkernel=\"/boot/vmlinuz-6.1\"\n\
+                    initrd=\"/boot/intel-ucode.img /boot/initramfs-6.1.img\"\n";
+        assert_eq!(
+            initrd_images(info),
+            strings(&["/boot/intel-ucode.img", "/boot/initramfs-6.1.img"])
+        );
+        assert!(initrd_images("index=0\n").is_empty());
+        let info = "initrd=\"/boot/initramfs-6.1.img $tuned_initrd\"\n";
+        let images = initrd_images(info);
+        assert_eq!(
+            images,
+            strings(&["/boot/initramfs-6.1.img", "$tuned_initrd"])
+        );
+        assert_eq!(main_initrd(&images), Some(0));
+    }
+
+    #[test]
+    fn replace_main_initrd() {
+        let images = strings(&["/boot/intel-ucode.img", "/boot/initramfs-6.1.img"]);
+        assert_eq!(
+            replace_initrd(images, Path::new("/boot/acpied.img")),
+            strings(&["/boot/intel-ucode.img", "/boot/acpied.img"])
+        );
+        assert_eq!(
+            replace_initrd(vec![], Path::new("/boot/acpied.img")),
+            strings(&["/boot/acpied.img"])
+        );
+        let images = strings(&[
+            "/boot/intel-ucode.img",
+            "/boot/initramfs-6.1.img",
+            "$tuned_initrd",
+        ]);
+        assert_eq!(
+            replace_initrd(images, Path::new("/boot/acpied.img")),
+            strings(&["/boot/intel-ucode.img", "/boot/acpied.img", "$tuned_initrd"])
+        );
+        assert_eq!(
+            replace_initrd(strings(&["$tuned_initrd"]), Path::new("/boot/acpied.img")),
+            strings(&["/boot/acpied.img", "$tuned_initrd"])
+        );
+    }
+
+    #[test]
+    fn split_quoted_args() {
+        assert_eq!(
+            split_args("ro  quiet acpi_osi=\"Windows 2015\" acpi_osi=! \"root=/dev/a b\"\n"),
+            strings(&[
+                "ro",
+                "quiet",
+                "acpi_osi=\"Windows 2015\"",
+                "acpi_osi=!",
+                "\"root=/dev/a b\"",
+            ])
+        );
+        assert!(split_args("  ").is_empty());
+    }
+
+    #[test]
+    fn arg_keys() {
+        assert_eq!(arg_key("acpi_osi=\"Windows 2015\""), "acpi_osi");
+        assert_eq!(arg_key("quiet"), "quiet");
+        assert_eq!(
+            removed_keys(
+                &strings(&["acpi_osi=Linux", "acpi_osi=!"]),
+                &strings(&["acpi_backlight"])
+            ),
+            strings(&["acpi_osi", "acpi_backlight"])
+        );
+    }
+
+    fn entry(text: &str) -> BlsEntry {
+        BlsEntry {
+            path: PathBuf::from("/boot/loader/entries/abc-6.1.conf"),
+            lines: text.lines().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn bls_set_initrd_keeps_microcode() {
+        let mut e =
+            entry("title Linux\nlinux /vmlinuz-6.1\ninitrd /intel-ucode.img /initramfs-6.1.img\n");
+        assert_eq!(e.initrd(), Some("/initramfs-6.1.img"));
+        e.set_initrd("/acpied.img");
+        assert_eq!(e.get("initrd"), Some("/intel-ucode.img /acpied.img"));
+        assert_eq!(e.lines.len(), 3);
+        let mut e = entry("linux /vmlinuz-6.1\ninitrd /initramfs-6.1.img $tuned_initrd\n");
+        assert_eq!(e.initrd(), Some("/initramfs-6.1.img"));
+        e.set_initrd("/acpied.img");
+        assert_eq!(e.get("initrd"), Some("/acpied.img $tuned_initrd"));
+    }
+
+    #[test]
+    fn bls_set_initrd_adds_line() {
+        let mut e = entry("title Linux\nlinux /vmlinuz-6.1\n");
+        assert_eq!(e.initrd(), None);
+        e.set_initrd("/acpied.img");
+        assert_eq!(e.get("initrd"), Some("/acpied.img"));
+    }
+
+    #[test]
+    fn entries_sorted_by_version() {
+        let mut names = vec![
+            "abc-6.10.0-1.fc40.x86_64.conf",
+            "abc-6.9.5-2.fc40.x86_64.conf",
+            "abc-6.9.12-1.fc40.x86_64.conf",
+            "abc-0-rescue.conf",
+        ];
+        names.sort_by(|a, b| version_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "abc-0-rescue.conf",
+                "abc-6.9.5-2.fc40.x86_64.conf",
+                "abc-6.9.12-1.fc40.x86_64.conf",
+                "abc-6.10.0-1.fc40.x86_64.conf",
+            ]
+        );
+        assert_eq!(version_cmp("6.1", "6.01"), Ordering::Equal);
+        assert_eq!(version_cmp("6.1a", "6.1"), Ordering::Greater);
+    }
+}
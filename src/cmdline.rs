@@ -0,0 +1,99 @@
+use crate::bootloader;
+use anyhow::{bail, Result};
+
+/// An ACPI related kernel command line parameter.
+pub struct Param {
+    pub key: &'static str,
+    pub description: &'static str,
+}
+
+/// Parameters acpied manages, see Documentation/admin-guide/kernel-parameters.txt.
+pub const PARAMS: [Param; 6] = [
+    Param {
+        key: "acpi_osi",
+        description: "_OSI strings: =Linux adds one, =!Windows 2012 removes one, =! drops all built-in ones, empty disables _OSI",
+    },
+    Param {
+        key: "acpi_mask_gpe",
+        description: "GPE number to mask, e.g. 0x6E, to stop a GPE storm; may be given several times",
+    },
+    Param {
+        key: "acpi_backlight",
+        description: "backlight interface to use: vendor, video, native or none",
+    },
+    Param {
+        key: "acpi.debug_layer",
+        description: "ACPICA components to debug, as a bit mask (CONFIG_ACPI_DEBUG)",
+    },
+    Param {
+        key: "acpi.debug_level",
+        description: "ACPICA debug message levels, as a bit mask (CONFIG_ACPI_DEBUG)",
+    },
+    Param {
+        key: "acpi_enforce_resources",
+        description: "drivers touching ACPI operation regions: strict, lax or no",
+    },
+];
+
+pub fn param(key: &str) -> Result<&'static Param> {
+    match PARAMS.iter().find(|p| p.key == key) {
+        Some(param) => Ok(param),
+        None => bail!(
+            "{} is not a managed ACPI parameter, use one of: {}",
+            key,
+            PARAMS.map(|p| p.key).join(", ")
+        ),
+    }
+}
+
+/// Arguments of each managed parameter on the command line of `kernel`, in
+/// the order of `PARAMS`.
+pub fn current(kernel: &str) -> Result<Vec<Vec<String>>> {
+    let args = bootloader::kernel_args(kernel)?;
+    Ok(PARAMS
+        .iter()
+        .map(|p| {
+            args.iter()
+                .filter(|a| bootloader::arg_key(a) == p.key)
+                .cloned()
+                .collect()
+        })
+        .collect())
+}
+
+/// Replace all arguments of the parameters in `set` by `set`, and drop the
+/// parameters keyed `unset`, on the command line of `kernel`. Returns the
+/// commands run.
+pub fn update(kernel: &str, set: &[String], unset: &[String]) -> Result<Vec<String>> {
+    for arg in set.iter().chain(unset.iter()) {
+        param(bootloader::arg_key(arg))?;
+    }
+    let commands = bootloader::update_args_commands(kernel, set, unset)?;
+    bootloader::update_args(kernel, set, unset)?;
+    Ok(commands)
+}
+
+/// Show or change the ACPI parameters of a boot entry from the command line.
+pub fn run(kernel: Option<&String>, set: &[String], unset: &[String]) -> Result<()> {
+    let kernel = match kernel {
+        Some(kernel) => kernel.clone(),
+        None => bootloader::default_kernel()?,
+    };
+    if !set.is_empty() || !unset.is_empty() {
+        for command in update(&kernel, set, unset)? {
+            println!("{}", command);
+        }
+    }
+
+    println!("boot entry: {}", kernel);
+    for (param, args) in PARAMS.iter().zip(current(&kernel)?) {
+        let value = if args.is_empty() {
+            "(not set)".to_string()
+        } else {
+            args.join(" ")
+        };
+        println!("{:<24}{}", param.key, value);
+        println!("{:<24}{}", "", param.description);
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const SYSTEM_CONFIG: &str = "/etc/acpied.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// External programs acpied runs.
+#[derive(Debug, Clone)]
+pub struct Tools {
+    pub iasl: PathBuf,
+    pub acpidump: PathBuf,
+    pub acpixtract: PathBuf,
+    pub grubby: PathBuf,
+    pub git: PathBuf,
+    pub patch: PathBuf,
+    pub init_script: PathBuf,
+}
+
+/// Paths acpied works with.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub root: PathBuf,
+    pub workspace: PathBuf,
+    pub log: PathBuf,
+    pub boot_dir: PathBuf,
+    pub tools: Tools,
+}
+
+/// Only root can dump and apply tables, other users work on imported dumps
+/// and keep their files in their data directory.
+fn user_data_dir() -> Option<PathBuf> {
+    let is_root = fs::metadata("/proc/self").map_or(true, |m| m.uid() == 0);
+    if is_root {
+        return None;
+    }
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .map(|dir| dir.join("acpied"))
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let data_dir = user_data_dir();
+        Self {
+            root: PathBuf::from("/"),
+            workspace: data_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("/var/lib/acpied")),
+            log: data_dir
+                .map(|dir| dir.join("acpied.log"))
+                .unwrap_or_else(|| PathBuf::from("/var/log/acpied.log")),
+            boot_dir: PathBuf::from("/boot"),
+            tools: Tools {
+                iasl: PathBuf::from("iasl"),
+                acpidump: PathBuf::from("acpidump"),
+                acpixtract: PathBuf::from("acpixtract"),
+                grubby: PathBuf::from("grubby"),
+                git: PathBuf::from("git"),
+                patch: PathBuf::from("patch"),
+                init_script: PathBuf::from("/bin/acpied-init"),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ToolsFile {
+    iasl: Option<PathBuf>,
+    acpidump: Option<PathBuf>,
+    acpixtract: Option<PathBuf>,
+    grubby: Option<PathBuf>,
+    git: Option<PathBuf>,
+    patch: Option<PathBuf>,
+    init_script: Option<PathBuf>,
+}
+
+/// Contents of a config file, every key is optional.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    root: Option<PathBuf>,
+    workspace: Option<PathBuf>,
+    log: Option<PathBuf>,
+    boot_dir: Option<PathBuf>,
+    #[serde(default)]
+    tools: ToolsFile,
+}
+
+fn set(value: &mut PathBuf, new: Option<PathBuf>) {
+    if let Some(new) = new {
+        *value = new;
+    }
+}
+
+impl Config {
+    fn merge(&mut self, file: ConfigFile) {
+        set(&mut self.root, file.root);
+        set(&mut self.workspace, file.workspace);
+        set(&mut self.log, file.log);
+        set(&mut self.boot_dir, file.boot_dir);
+        set(&mut self.tools.iasl, file.tools.iasl);
+        set(&mut self.tools.acpidump, file.tools.acpidump);
+        set(&mut self.tools.acpixtract, file.tools.acpixtract);
+        set(&mut self.tools.grubby, file.tools.grubby);
+        set(&mut self.tools.git, file.tools.git);
+        set(&mut self.tools.patch, file.tools.patch);
+        set(&mut self.tools.init_script, file.tools.init_script);
+    }
+
+    fn merge_file(&mut self, path: &Path) -> Result<()> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("fail to read {}", path.display()))?;
+        let file: ConfigFile =
+            toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+        self.merge(file);
+        Ok(())
+    }
+
+    /// Build the configuration from the defaults, the system and user config
+    /// files and finally the command line, later sources taking precedence.
+    pub fn load(args: &ArgMatches) -> Result<Self> {
+        let mut config = Self::default();
+        let user_config = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("acpied.toml"));
+        for path in [Some(PathBuf::from(SYSTEM_CONFIG)), user_config]
+            .into_iter()
+            .flatten()
+        {
+            if path.exists() {
+                config.merge_file(&path)?;
+            }
+        }
+        if let Some(path) = args.get_one::<PathBuf>("config") {
+            config.merge_file(path)?;
+        }
+
+        let arg = |name: &str| args.get_one::<PathBuf>(name).cloned();
+        config.merge(ConfigFile {
+            root: arg("root"),
+            workspace: arg("workspace"),
+            log: arg("log"),
+            boot_dir: arg("boot-dir"),
+            tools: ToolsFile {
+                iasl: arg("iasl"),
+                acpidump: arg("acpidump"),
+                acpixtract: arg("acpixtract"),
+                grubby: arg("grubby"),
+                git: arg("git"),
+                patch: arg("patch"),
+                init_script: arg("init-script"),
+            },
+        });
+        Ok(config)
+    }
+
+    /// Whether acpied works on the running system rather than a tree mounted
+    /// somewhere else.
+    pub fn is_live(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// Resolve a system path, such as /boot or /sys, under the root.
+    pub fn system_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+/// Make `config` the configuration of this process.
+pub fn init(config: Config) -> Result<()> {
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow!("configuration already initialized"))
+}
+
+/// Configuration of this process, the defaults if none was set.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
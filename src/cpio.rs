@@ -0,0 +1,169 @@
+use anyhow::{bail, Result};
+
+const MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+const BLOCK_SIZE: usize = 512;
+
+/// An entry of a newc archive; `None` data marks a directory.
+pub struct Entry {
+    pub name: String,
+    pub data: Option<Vec<u8>>,
+}
+
+fn pad(buf: &mut Vec<u8>, align: usize) {
+    while !buf.len().is_multiple_of(align) {
+        buf.push(0);
+    }
+}
+
+fn write_entry(buf: &mut Vec<u8>, ino: usize, name: &str, mode: u32, data: &[u8]) {
+    let fields = [
+        ino as u32,
+        mode,
+        0,
+        0,
+        if mode & 0o040000 != 0 { 2 } else { 1 },
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    buf.extend_from_slice(MAGIC);
+    for field in fields.iter() {
+        buf.extend_from_slice(format!("{:08X}", field).as_bytes());
+    }
+    buf.extend_from_slice(name.as_bytes());
+    buf.push(0);
+    pad(buf, 4);
+    buf.extend_from_slice(data);
+    pad(buf, 4);
+}
+
+/// Build an uncompressed newc archive, as produced by `cpio -H newc --create`.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut buf = vec![];
+    for (ino, entry) in entries.iter().enumerate() {
+        match &entry.data {
+            Some(data) => write_entry(&mut buf, ino + 1, &entry.name, 0o100644, data),
+            None => write_entry(&mut buf, ino + 1, &entry.name, 0o040755, &[]),
+        }
+    }
+    write_entry(&mut buf, 0, TRAILER, 0, &[]);
+    pad(&mut buf, BLOCK_SIZE);
+    buf
+}
+
+fn field(header: &[u8], index: usize) -> Result<usize> {
+    let start = MAGIC.len() + index * 8;
+    let text = std::str::from_utf8(&header[start..start + 8])?;
+    Ok(usize::from_str_radix(text, 16)?)
+}
+
+fn align(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Parse the newc archive at the start of `data`, stopping at the trailer.
+pub fn parse(data: &[u8]) -> Result<Vec<Entry>> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    loop {
+        if data.len() < offset + HEADER_SIZE || &data[offset..offset + 6] != MAGIC {
+            bail!("bad cpio header at offset {}", offset);
+        }
+        let header = &data[offset..offset + HEADER_SIZE];
+        let mode = field(header, 1)?;
+        let size = field(header, 6)?;
+        let name_size = field(header, 11)?;
+        let name_start = offset + HEADER_SIZE;
+        let data_start = align(name_start + name_size);
+        if name_size == 0 || data.len() < data_start + size {
+            bail!("truncated cpio entry at offset {}", offset);
+        }
+        let name = String::from_utf8_lossy(&data[name_start..name_start + name_size - 1]);
+        if name == TRAILER {
+            return Ok(entries);
+        }
+        entries.push(Entry {
+            name: name.to_string(),
+            data: if mode & 0o040000 != 0 {
+                None
+            } else {
+                Some(data[data_start..data_start + size].to_vec())
+            },
+        });
+        offset = align(data_start + size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                name: "kernel".to_string(),
+                data: None,
+            },
+            Entry {
+                name: "kernel/firmware/acpi/ssdt1.aml".to_string(),
+                // odd length, so the data is padded
+                data: Some(b"SSDT\x25\x00\x00\x00\x02".to_vec()),
+            },
+        ]
+    }
+
+    #[test]
+    fn build_then_parse() {
+        let archive = build(&entries());
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        let parsed = parse(&archive).unwrap();
+        assert_eq!(parsed.len(), 2);
+        for (parsed, entry) in parsed.iter().zip(entries()) {
+            assert_eq!(parsed.name, entry.name);
+            assert_eq!(parsed.data, entry.data);
+        }
+    }
+
+    #[test]
+    fn parse_stops_at_trailer() {
+        let mut data = build(&entries());
+        data.extend_from_slice(b"compressed initrd follows");
+        assert_eq!(parse(&data).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn truncated_entry() {
+        let archive = build(&entries());
+        // the header of the second entry is whole, its data cut short
+        let second = align(HEADER_SIZE + "kernel".len() + 1);
+        let end = align(second + HEADER_SIZE + "kernel/firmware/acpi/ssdt1.aml".len() + 1) + 4;
+        let e = parse(&archive[..end]).err().unwrap();
+        assert!(e.to_string().contains("truncated cpio entry"), "{}", e);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let archive = build(&entries());
+        let e = parse(&archive[..HEADER_SIZE - 1]).err().unwrap();
+        assert!(
+            e.to_string().contains("bad cpio header at offset 0"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn missing_trailer() {
+        let archive = build(&entries()[..1]);
+        let end = align(HEADER_SIZE + "kernel".len() + 1);
+        let e = parse(&archive[..end]).err().unwrap();
+        assert!(e.to_string().contains("bad cpio header"), "{}", e);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::apply;
+use crate::workspace;
+use anyhow::{Context, Result};
+use similar::{capture_diff_slices, Algorithm, ChangeTag, DiffOp, TextDiff};
+use std::fs;
+use std::ops::Range;
+
+/// Lines of context around each change.
+const CONTEXT: usize = 3;
+
+/// What a line of a diff shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Header,
+    Hunk,
+    Equal,
+    Delete,
+    Insert,
+    /// A side by side line whose sides differ.
+    Replace,
+}
+
+/// A diff ready to print, with the hunks located for navigation.
+pub struct Diff {
+    pub lines: Vec<(Tag, String)>,
+    /// Index in `lines` of each hunk header.
+    pub hunks: Vec<usize>,
+}
+
+/// How a change shows in the editor gutter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    Added,
+    Changed,
+    /// Lines removed before the marked one.
+    Deleted,
+}
+
+/// Lines of the firmware source replaced by lines of the edited one.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl Change {
+    pub fn marker(&self) -> Marker {
+        if self.new.is_empty() {
+            Marker::Deleted
+        } else if self.old.is_empty() {
+            Marker::Added
+        } else {
+            Marker::Changed
+        }
+    }
+
+    /// First line of the edited source marked, clamped to the `len` lines.
+    pub fn line(&self, len: usize) -> usize {
+        self.new.start.min(len.saturating_sub(1))
+    }
+
+    /// Whether the change is marked on `line`.
+    pub fn contains(&self, line: usize, len: usize) -> bool {
+        self.new.contains(&line) || (self.new.is_empty() && self.line(len) == line)
+    }
+}
+
+/// Changes from the `origin` lines to the edited `lines`, adjacent deletions
+/// and insertions merged.
+pub fn changes(origin: &[String], lines: &[String]) -> Vec<Change> {
+    let mut changes: Vec<Change> = vec![];
+    for op in capture_diff_slices(Algorithm::Myers, origin, lines) {
+        if let DiffOp::Equal { .. } = op {
+            continue;
+        }
+        match changes.last_mut() {
+            Some(last)
+                if last.old.end == op.old_range().start && last.new.end == op.new_range().start =>
+            {
+                last.old.end = op.old_range().end;
+                last.new.end = op.new_range().end;
+            }
+            _ => changes.push(Change {
+                old: op.old_range(),
+                new: op.new_range(),
+            }),
+        }
+    }
+    changes
+}
+
+/// Firmware and edited source of a table. A table the firmware does not have
+/// is compared with an empty file.
+pub fn sources(dsl_file: &str) -> Result<(String, String)> {
+    let origin = fs::read_to_string(workspace::origin_dir().join(dsl_file)).unwrap_or_default();
+    let modified_path = workspace::modified_dir().join(dsl_file);
+    let modified = fs::read_to_string(&modified_path)
+        .with_context(|| format!("fail to read {}", modified_path.display()))?;
+    Ok((origin, modified))
+}
+
+fn hunk_header(ops: &[DiffOp]) -> String {
+    let (first, last) = (&ops[0], &ops[ops.len() - 1]);
+    let old = first.old_range().start..last.old_range().end;
+    let new = first.new_range().start..last.new_range().end;
+    // an empty range starts at the line before it
+    let start = |range: &std::ops::Range<usize>| range.start + usize::from(!range.is_empty());
+    format!(
+        "@@ -{},{} +{},{} @@",
+        start(&old),
+        old.len(),
+        start(&new),
+        new.len()
+    )
+}
+
+/// Unified diff of the firmware and the edited source of `dsl_file`.
+pub fn unified(dsl_file: &str) -> Result<Diff> {
+    let (origin, modified) = sources(dsl_file)?;
+    let diff = TextDiff::from_lines(&origin, &modified);
+    let mut result = Diff {
+        lines: vec![
+            (Tag::Header, format!("--- origin/{}", dsl_file)),
+            (Tag::Header, format!("+++ modified/{}", dsl_file)),
+        ],
+        hunks: vec![],
+    };
+    for ops in diff.grouped_ops(CONTEXT) {
+        result.hunks.push(result.lines.len());
+        result.lines.push((Tag::Hunk, hunk_header(&ops)));
+        for op in ops.iter() {
+            for change in diff.iter_changes(op) {
+                let (tag, sign) = match change.tag() {
+                    ChangeTag::Equal => (Tag::Equal, ' '),
+                    ChangeTag::Delete => (Tag::Delete, '-'),
+                    ChangeTag::Insert => (Tag::Insert, '+'),
+                };
+                let line = change.value().trim_end_matches(['\r', '\n']);
+                result.lines.push((tag, format!("{}{}", sign, line)));
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn column(text: &str, width: usize) -> String {
+    let text: String = text.chars().take(width).collect();
+    format!("{:<width$}", text, width = width)
+}
+
+/// Side by side diff of `dsl_file` fitting in `width` columns, the firmware
+/// source on the left.
+pub fn side_by_side(dsl_file: &str, width: usize) -> Result<Diff> {
+    let (origin, modified) = sources(dsl_file)?;
+    let diff = TextDiff::from_lines(&origin, &modified);
+    let half = width.saturating_sub(3) / 2;
+    let row = |left: &str, mark: char, right: &str| {
+        format!("{} {} {}", column(left, half), mark, column(right, half))
+    };
+    let mut result = Diff {
+        lines: vec![(
+            Tag::Header,
+            row(
+                &format!("origin/{}", dsl_file),
+                ' ',
+                &format!("modified/{}", dsl_file),
+            ),
+        )],
+        hunks: vec![],
+    };
+    for ops in diff.grouped_ops(CONTEXT) {
+        result.hunks.push(result.lines.len());
+        result.lines.push((Tag::Hunk, hunk_header(&ops)));
+        for op in ops.iter() {
+            let mut deleted = vec![];
+            let mut inserted = vec![];
+            for change in diff.iter_changes(op) {
+                let line = change.value().trim_end_matches(['\r', '\n']).to_string();
+                match change.tag() {
+                    ChangeTag::Equal => result.lines.push((Tag::Equal, row(&line, ' ', &line))),
+                    ChangeTag::Delete => deleted.push(line),
+                    ChangeTag::Insert => inserted.push(line),
+                }
+            }
+            for i in 0..deleted.len().max(inserted.len()) {
+                let line = match (deleted.get(i), inserted.get(i)) {
+                    (Some(old), Some(new)) => (Tag::Replace, row(old, '|', new)),
+                    (Some(old), None) => (Tag::Delete, row(old, '<', "")),
+                    (None, Some(new)) => (Tag::Insert, row("", '>', new)),
+                    (None, None) => continue,
+                };
+                result.lines.push(line);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Print the diff of the given tables, or of all modified tables, from the
+/// command line.
+pub fn run(tables: &[String], side_by_side: Option<usize>) -> Result<()> {
+    let tables = if tables.is_empty() {
+        workspace::modified()?
+    } else {
+        tables.to_vec()
+    };
+    for table in tables.iter() {
+        let dsl_file = format!("{}.dsl", apply::stem(table));
+        let diff = match side_by_side {
+            Some(width) => self::side_by_side(&dsl_file, width)?,
+            None => unified(&dsl_file)?,
+        };
+        if diff.hunks.is_empty() {
+            continue;
+        }
+        for (_, line) in diff.lines.iter() {
+            println!("{}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(old: &str, new: &str, context: usize) -> Vec<String> {
+        TextDiff::from_lines(old, new)
+            .grouped_ops(context)
+            .iter()
+            .map(|ops| hunk_header(ops))
+            .collect()
+    }
+
+    #[test]
+    fn hunk_headers() {
+        assert_eq!(headers("a\nb\n", "a\nc\n", CONTEXT), ["@@ -1,2 +1,2 @@"]);
+        // a new table and a table emptied
+        assert_eq!(headers("", "a\nb\n", CONTEXT), ["@@ -0,0 +1,2 @@"]);
+        assert_eq!(headers("a\nb\n", "", CONTEXT), ["@@ -1,2 +0,0 @@"]);
+        // without context an empty range starts at the line before it
+        assert_eq!(headers("a\nb\nc\n", "a\nx\nb\nc\n", 0), ["@@ -1,0 +2,1 @@"]);
+        assert_eq!(headers("a\nb\nc\n", "a\nc\n", 0), ["@@ -2,1 +1,0 @@"]);
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn merged_changes() {
+        let origin = lines("a\nb\nc\nd\ne");
+        let edited = lines("a\nB\nc\nx\nd");
+        let found: Vec<(Range<usize>, Range<usize>, Marker)> = changes(&origin, &edited)
+            .into_iter()
+            .map(|c| (c.old.clone(), c.new.clone(), c.marker()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (1..2, 1..2, Marker::Changed),
+                (3..3, 3..4, Marker::Added),
+                (4..5, 5..5, Marker::Deleted),
+            ]
+        );
+    }
+
+    #[test]
+    fn deleted_marker_line() {
+        let origin = lines("a\nb\nc");
+        let edited = lines("a");
+        let change = &changes(&origin, &edited)[0];
+        assert_eq!(change.marker(), Marker::Deleted);
+        // marked on the last line left
+        assert_eq!(change.line(edited.len()), 0);
+        assert!(change.contains(0, edited.len()));
+    }
+}
@@ -0,0 +1,309 @@
+//! Light scanning of disassembled ASL, enough to tell which named objects a
+//! table declares and where control methods start and end.
+
+/// Kind of a declared object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Device,
+    Method,
+    FieldUnit,
+    Other,
+}
+
+/// A named object declared in the source.
+#[derive(Debug, Clone)]
+pub struct Object {
+    /// Absolute namespace path, e.g. `\_SB.PCI0._INI`.
+    pub path: String,
+    pub kind: Kind,
+    /// Line of the declaration.
+    pub start: usize,
+    /// Line closing the object's block, or `start` if it has none.
+    pub end: usize,
+}
+
+/// Keywords declaring an object named by their first argument.
+const DECLARATIONS: [(&str, Kind); 9] = [
+    ("Device", Kind::Device),
+    ("Method", Kind::Method),
+    ("Name", Kind::Other),
+    ("OperationRegion", Kind::Other),
+    ("Mutex", Kind::Other),
+    ("Event", Kind::Other),
+    ("ThermalZone", Kind::Other),
+    ("Processor", Kind::Other),
+    ("PowerResource", Kind::Other),
+];
+
+/// Declarations whose block is a scope for the objects inside.
+const SCOPES: [&str; 5] = [
+    "Device",
+    "Method",
+    "ThermalZone",
+    "Processor",
+    "PowerResource",
+];
+
+/// Keywords whose block holds field units rather than a new scope.
+const FIELDS: [&str; 3] = ["Field", "IndexField", "BankField"];
+
+/// What the next `{` opens.
+enum Opener {
+    /// A block opening a scope, with the index of its object if declared.
+    Scope(String, Option<usize>),
+    Field,
+    Other,
+}
+
+struct Frame {
+    scope: Option<String>,
+    object: Option<usize>,
+    is_method: bool,
+    is_field: bool,
+}
+
+/// Drop comments and string literals so braces and names inside them are
+/// not mistaken for code.
+fn strip(line: &str, in_comment: &mut bool) -> String {
+    let mut code = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if *in_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_comment = false;
+            }
+        } else if in_string {
+            if c == '\\' {
+                chars.next();
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            break;
+        } else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            *in_comment = true;
+        } else {
+            code.push(c);
+        }
+    }
+    code
+}
+
+/// Keyword and first argument of a line such as `Method (_STA, 0, ...)`.
+fn keyword(code: &str) -> Option<(&str, &str)> {
+    let code = code.trim_start();
+    let (keyword, rest) = code.split_once('(')?;
+    let keyword = keyword.trim_end();
+    if keyword.is_empty() || !keyword.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let end = rest.find([',', ')']).unwrap_or(rest.len());
+    Some((keyword, rest[..end].trim()))
+}
+
+/// Resolve `name` relative to `scope`, both in ASL notation.
+pub fn resolve(scope: &str, name: &str) -> String {
+    if name.starts_with('\\') {
+        return name.to_string();
+    }
+    let mut segments: Vec<&str> = scope
+        .trim_start_matches('\\')
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let mut name = name;
+    while let Some(rest) = name.strip_prefix('^') {
+        segments.pop();
+        name = rest;
+    }
+    segments.extend(name.split('.').filter(|s| !s.is_empty()));
+    format!("\\{}", segments.join("."))
+}
+
+/// Field unit declared on a line of a Field block, e.g. `ECWR,   8,`.
+fn field_unit(code: &str) -> Option<&str> {
+    let (name, _) = code.trim().split_once(',')?;
+    let name = name.trim();
+    let valid = !name.is_empty()
+        && name.len() <= 4
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    valid.then_some(name)
+}
+
+/// Named objects declared in `lines`, leaving out those local to control
+/// methods.
+pub fn scan(lines: &[String]) -> Vec<Object> {
+    let mut objects: Vec<Object> = vec![];
+    let mut stack: Vec<Frame> = vec![];
+    let mut opener = Opener::Other;
+    let mut in_comment = false;
+
+    for (index, line) in lines.iter().enumerate() {
+        let code = strip(line, &mut in_comment);
+        let scope = stack
+            .iter()
+            .rev()
+            .find_map(|f| f.scope.clone())
+            .unwrap_or_else(|| "\\".to_string());
+        let in_method = stack.iter().any(|f| f.is_method);
+        let in_field = stack.last().is_some_and(|f| f.is_field);
+
+        if let Some((word, name)) = keyword(&code).filter(|_| !in_method) {
+            if let Some((_, kind)) = DECLARATIONS.iter().find(|(k, _)| *k == word) {
+                let path = resolve(&scope, name);
+                objects.push(Object {
+                    path: path.clone(),
+                    kind: *kind,
+                    start: index,
+                    end: index,
+                });
+                opener = if SCOPES.contains(&word) {
+                    Opener::Scope(path, Some(objects.len() - 1))
+                } else {
+                    Opener::Other
+                };
+            } else if word == "Scope" {
+                opener = Opener::Scope(resolve(&scope, name), None);
+            } else if FIELDS.contains(&word) {
+                opener = Opener::Field;
+            } else {
+                opener = Opener::Other;
+            }
+        } else if in_field {
+            if let Some(name) = field_unit(&code) {
+                objects.push(Object {
+                    path: resolve(&scope, name),
+                    kind: Kind::FieldUnit,
+                    start: index,
+                    end: index,
+                });
+            }
+        }
+
+        for c in code.chars() {
+            match c {
+                '{' => {
+                    let frame = match std::mem::replace(&mut opener, Opener::Other) {
+                        Opener::Scope(path, object) => Frame {
+                            scope: Some(path),
+                            is_method: object.is_some_and(|o| objects[o].kind == Kind::Method),
+                            object,
+                            is_field: false,
+                        },
+                        Opener::Field => Frame {
+                            scope: None,
+                            object: None,
+                            is_method: false,
+                            is_field: true,
+                        },
+                        Opener::Other => Frame {
+                            scope: None,
+                            object: None,
+                            is_method: false,
+                            is_field: false,
+                        },
+                    };
+                    stack.push(frame);
+                }
+                '}' => {
+                    if let Some(object) = stack.pop().and_then(|f| f.object) {
+                        objects[object].end = index;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    objects
+}
+
+/// Innermost control method whose block holds `line`.
+pub fn method_at(objects: &[Object], line: usize) -> Option<&Object> {
+    objects
+        .iter()
+        .filter(|o| o.kind == Kind::Method && o.start <= line && line <= o.end)
+        .max_by_key(|o| o.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"DefinitionBlock ("", "DSDT", 2, "OEMID", "TABLEID", 0x00000001)
+{
+    Scope (_SB)
+    {
+        Device (PCI0)
+        {
+            OperationRegion (ECOR, EmbeddedControl, Zero, 0xFF)
+            Field (ECOR, ByteAcc, Lock, Preserve)
+            {
+                ECWR,   8,
+                Offset (0x10),
+                ECRD,   8
+            }
+            Method (_STA, 0, NotSerialized)  // _STA: Status
+            {
+                Name (LOCL, "}")
+                If (ECWR) { Return (0x0F) }
+                Return (Zero)
+            }
+        }
+        Method (\_SB.PCI0.XINI, 0) /* { */
+        {
+        }
+    }
+}"#;
+
+    fn objects() -> Vec<Object> {
+        let lines: Vec<String> = SOURCE.lines().map(String::from).collect();
+        scan(&lines)
+    }
+
+    #[test]
+    fn scan_declarations() {
+        let found: Vec<(String, Kind, usize, usize)> = objects()
+            .into_iter()
+            .map(|o| (o.path, o.kind, o.start, o.end))
+            .collect();
+        let expected = [
+            ("\\_SB.PCI0", Kind::Device, 4, 19),
+            ("\\_SB.PCI0.ECOR", Kind::Other, 6, 6),
+            ("\\_SB.PCI0.ECWR", Kind::FieldUnit, 9, 9),
+            ("\\_SB.PCI0.ECRD", Kind::FieldUnit, 11, 11),
+            ("\\_SB.PCI0._STA", Kind::Method, 13, 18),
+            ("\\_SB.PCI0.XINI", Kind::Method, 20, 22),
+        ];
+        let expected: Vec<(String, Kind, usize, usize)> = expected
+            .iter()
+            .map(|(p, k, s, e)| (p.to_string(), *k, *s, *e))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn method_at_line() {
+        let objects = objects();
+        let method = |line| method_at(&objects, line).map(|o| o.path.as_str());
+        assert_eq!(method(13), Some("\\_SB.PCI0._STA"));
+        assert_eq!(method(16), Some("\\_SB.PCI0._STA"));
+        assert_eq!(method(19), None);
+        assert_eq!(method(21), Some("\\_SB.PCI0.XINI"));
+        assert_eq!(method(2), None);
+    }
+
+    #[test]
+    fn resolve_paths() {
+        assert_eq!(resolve("\\", "_SB"), "\\_SB");
+        assert_eq!(resolve("\\_SB.PCI0", "LPCB.EC0"), "\\_SB.PCI0.LPCB.EC0");
+        assert_eq!(resolve("\\_SB.PCI0", "^EC0"), "\\_SB.EC0");
+        assert_eq!(resolve("\\_SB.PCI0", "\\_GPE._L6E"), "\\_GPE._L6E");
+    }
+}
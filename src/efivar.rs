@@ -0,0 +1,301 @@
+use crate::apply::{self, Validation};
+use crate::bootloader;
+use crate::config;
+use crate::journal::{self, Entry};
+use crate::workspace;
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const EFIVARS: &str = "/sys/firmware/efi/efivars";
+
+/// Name of the variables holding the SSDTs, passed as `efivar_ssdt=`.
+pub const VARIABLE: &str = "acpied";
+
+/// EFI_VARIABLE_NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS
+const ATTRIBUTES: u32 = 0x7;
+
+/// Inode flag ioctls and the immutable flag, as used by `chattr`.
+const FS_IOC_GETFLAGS: libc::c_ulong = 0x80086601;
+const FS_IOC_SETFLAGS: libc::c_ulong = 0x40086602;
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+fn efivars_dir() -> PathBuf {
+    config::get().system_path(EFIVARS)
+}
+
+/// Clear the immutable flag efivarfs sets on the variables it creates, like
+/// `chattr -i`, so they can be overwritten or removed. Does nothing for a
+/// missing file or a file system without inode flags.
+fn make_mutable(path: &Path) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("fail to open {}", path.display())),
+    };
+    let mut flags: libc::c_int = 0;
+    // SAFETY: the ioctls read and write a single int through a valid pointer
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS as _, &mut flags) } != 0 {
+        return match io::Error::last_os_error().raw_os_error() {
+            Some(libc::ENOTTY | libc::EOPNOTSUPP | libc::EINVAL) => Ok(()),
+            _ => Err(io::Error::last_os_error())
+                .with_context(|| format!("fail to read the flags of {}", path.display())),
+        };
+    }
+    if flags & FS_IMMUTABLE_FL == 0 {
+        return Ok(());
+    }
+    flags &= !FS_IMMUTABLE_FL;
+    if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS as _, &flags) } != 0 {
+        return Err(io::Error::last_os_error()).with_context(|| {
+            format!(
+                "fail to clear the immutable flag of {} (chattr -i)",
+                path.display()
+            )
+        });
+    }
+    Ok(())
+}
+
+/// Write an efivarfs file, the attributes and the data in a single write as
+/// efivarfs wants.
+fn write_variable(path: &Path, data: &[u8]) -> Result<()> {
+    make_mutable(path)?;
+    fs::write(path, data).with_context(|| format!("fail to write {}", path.display()))
+}
+
+fn remove_variable(path: &Path) -> Result<()> {
+    make_mutable(path)?;
+    fs::remove_file(path).with_context(|| format!("fail to remove {}", path.display()))
+}
+
+/// Vendor GUID of the variable holding a table. The kernel loads every
+/// variable named `VARIABLE`, so each table gets its own GUID, derived from
+/// its name so a later apply overwrites it.
+fn vendor_guid(dsl_file: &str) -> String {
+    let hash = Sha256::digest(format!("{}:{}", VARIABLE, apply::stem(dsl_file)).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    // name based UUID, version 5
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Name of the variable holding a table, as efivarfs and the kernel log show
+/// it.
+pub fn variable_name(dsl_file: &str) -> String {
+    format!("{}-{}", VARIABLE, vendor_guid(dsl_file))
+}
+
+/// Variables written by an earlier apply.
+fn written_variables() -> Result<Vec<PathBuf>> {
+    let dir = efivars_dir();
+    let prefix = format!("{}-", VARIABLE);
+    let mut paths = vec![];
+    for entry in fs::read_dir(&dir).with_context(|| format!("fail to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with(&prefix))
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// An efivarfs file to write: the attributes followed by the AML.
+pub struct Variable {
+    pub dsl_file: String,
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+}
+
+/// Everything an EFI variable apply is going to do.
+pub struct Plan {
+    pub boot_entry: String,
+    pub validation: Validation,
+    pub variables: Vec<Variable>,
+    pub stale: Vec<PathBuf>,
+    pub commands: Vec<String>,
+}
+
+impl Plan {
+    /// Human readable description of the plan.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![format!("boot entry: {}", self.boot_entry)];
+        lines.push("tables:".to_string());
+        for line in self.validation.summary() {
+            lines.push(format!("  {}", line));
+        }
+        lines.push("efi variables:".to_string());
+        for variable in self.variables.iter() {
+            lines.push(format!(
+                "  {} ({} bytes) <- {}",
+                variable.path.display(),
+                variable.data.len(),
+                variable.dsl_file
+            ));
+        }
+        for path in self.stale.iter() {
+            lines.push(format!("  remove {}", path.display()));
+        }
+        lines.push("bootloader commands:".to_string());
+        for command in self.commands.iter() {
+            lines.push(format!("  {}", command));
+        }
+        lines
+    }
+}
+
+fn kernel_arg() -> String {
+    format!("efivar_ssdt={}", VARIABLE)
+}
+
+/// Work out what writing the validated tables to EFI variables would do.
+pub fn plan(validation: Validation) -> Result<Plan> {
+    if workspace::is_offline() {
+        bail!("tables imported from a dump can only be exported");
+    }
+    let dir = efivars_dir();
+    if !dir.is_dir() {
+        bail!("{} not found, is efivarfs mounted?", dir.display());
+    }
+
+    let mut variables = vec![];
+    for t in validation.tables.iter() {
+        if t.header.signature != "SSDT" {
+            bail!(
+                "{} is a {}, only SSDTs can be loaded from EFI variables",
+                t.dsl_file,
+                t.header.signature
+            );
+        }
+        let mut data = ATTRIBUTES.to_le_bytes().to_vec();
+        data.extend(fs::read(&t.aml)?);
+        variables.push(Variable {
+            dsl_file: t.dsl_file.clone(),
+            path: dir.join(variable_name(&t.dsl_file)),
+            data,
+        });
+    }
+    let stale = written_variables()?
+        .into_iter()
+        .filter(|path| !variables.iter().any(|v| &v.path == path))
+        .collect();
+
+    let boot_entry = bootloader::default_kernel()?;
+    let commands = bootloader::update_args_commands(&boot_entry, &[kernel_arg()], &[])?;
+    Ok(Plan {
+        boot_entry,
+        validation,
+        variables,
+        stale,
+        commands,
+    })
+}
+
+/// Write the variables, remove those of tables no longer applied and add
+/// `efivar_ssdt=` to the boot entry. If a step fails or the apply is
+/// cancelled before the boot entry is updated, the variables get their
+/// previous contents back and new ones are removed.
+pub fn install(plan: &Plan, progress: &dyn Fn(&str), cancel: &AtomicBool) -> Result<()> {
+    let mut changed = vec![];
+    let result = (|| -> Result<()> {
+        let cancelled = || {
+            if cancel.load(Ordering::SeqCst) {
+                bail!("cancelled");
+            }
+            Ok(())
+        };
+        for variable in plan.variables.iter() {
+            cancelled()?;
+            progress(&format!("writing {}", variable.path.display()));
+            let previous = fs::read(&variable.path).ok();
+            write_variable(&variable.path, &variable.data)?;
+            changed.push((&variable.path, previous));
+        }
+        for path in plan.stale.iter() {
+            cancelled()?;
+            progress(&format!("removing {}", path.display()));
+            let previous = fs::read(path).ok();
+            remove_variable(path)?;
+            changed.push((path, previous));
+        }
+        // the last chance to cancel is right before the bootloader is touched
+        cancelled()?;
+        progress("updating boot entry");
+        bootloader::update_args(&plan.boot_entry, &[kernel_arg()], &[])
+            .context("update boot entry failed")
+    })();
+    if let Err(e) = result {
+        for (path, previous) in changed.into_iter().rev() {
+            let restored = match previous {
+                Some(data) => write_variable(path, &data),
+                None => remove_variable(path),
+            };
+            if let Err(e) = restored {
+                progress(&format!("fail to roll back {}: {:#}", path.display(), e));
+            }
+        }
+        return Err(e.context("changes rolled back"));
+    }
+
+    if let Err(e) = workspace::record_applied(&plan.validation) {
+        progress(&format!("fail to record the applied tables: {:#}", e));
+    }
+    let entry = Entry::new("efivar", &plan.boot_entry, &plan.validation).map(|mut entry| {
+        entry.variables = plan.variables.iter().map(|v| v.path.clone()).collect();
+        entry
+    });
+    if let Err(e) = entry.and_then(journal::record) {
+        progress(&format!("fail to record the apply in the journal: {:#}", e));
+    }
+    Ok(())
+}
+
+/// Apply the modified tables as EFI variables from the command line.
+pub fn run(dry_run: bool) -> Result<()> {
+    let modified = workspace::modified()?;
+    let dsl_files = workspace::to_apply(&modified);
+    for dsl_file in modified.iter().filter(|f| !dsl_files.contains(f)) {
+        println!("{} excluded from apply", dsl_file);
+    }
+    if dsl_files.is_empty() {
+        println!("no modified tables to apply");
+        return Ok(());
+    }
+
+    let progress = |line: &str| println!("{}", line);
+    let cancel = AtomicBool::new(false);
+    let validation = apply::validate_workspace(&dsl_files, &progress, &cancel)?;
+    let plan = plan(validation)?;
+    for line in plan.describe() {
+        println!("{}", line);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    install(&plan, &progress, &cancel)?;
+    println!(
+        "{} tables written to efi variables for {}",
+        plan.variables.len(),
+        plan.boot_entry
+    );
+    Ok(())
+}
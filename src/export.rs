@@ -0,0 +1,192 @@
+use crate::apply::{self, Validation};
+use crate::iasl;
+use crate::workspace;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+/// Shape of the exported tables.
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    /// Uncompressed cpio archive to prepend to an initrd.
+    Cpio,
+    /// Directory of compiled .aml files.
+    Aml,
+    /// Tarball of compiled .aml files.
+    Tar,
+    /// DSDT as a C hex include for CONFIG_ACPI_CUSTOM_DSDT_FILE.
+    Kernel,
+    /// Directory of .aml files to pass to QEMU with -acpitable.
+    Qemu,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Cpio,
+        Format::Aml,
+        Format::Tar,
+        Format::Kernel,
+        Format::Qemu,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Cpio => "cpio",
+            Format::Aml => "aml",
+            Format::Tar => "tar",
+            Format::Kernel => "kernel",
+            Format::Qemu => "qemu",
+        }
+    }
+
+    /// Key choosing the format in the editor.
+    pub fn key(&self) -> char {
+        self.name().chars().next().unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Format::Cpio => "override cpio archive, to prepend to any initrd",
+            Format::Aml => "directory of compiled .aml files",
+            Format::Tar => "tarball of compiled .aml files",
+            Format::Kernel => "DSDT C hex include and kernel config fragment",
+            Format::Qemu => "compiled .aml files for QEMU -acpitable",
+        }
+    }
+
+    /// Output written when none is given.
+    pub fn default_output(&self) -> &'static str {
+        match self {
+            Format::Cpio => "acpi_override.cpio",
+            Format::Aml => "acpi_override",
+            Format::Tar => "acpi_override.tar",
+            Format::Kernel => "acpi_custom_dsdt",
+            Format::Qemu => "acpi_qemu",
+        }
+    }
+}
+
+fn aml_name(dsl_file: &str) -> String {
+    format!("{}.aml", apply::stem(dsl_file))
+}
+
+fn archive(validation: &Validation, output: &Path) -> Result<()> {
+    fs::write(output, apply::override_archive(validation)?)?;
+    Ok(())
+}
+
+fn aml_dir(validation: &Validation, output: &Path) -> Result<()> {
+    fs::create_dir_all(output)?;
+    for t in validation.tables.iter() {
+        fs::copy(&t.aml, output.join(aml_name(&t.dsl_file)))?;
+    }
+    Ok(())
+}
+
+fn tarball(validation: &Validation, output: &Path) -> Result<()> {
+    let mut builder = tar::Builder::new(File::create(output)?);
+    for t in validation.tables.iter() {
+        let mut aml = File::open(&t.aml)?;
+        builder.append_file(aml_name(&t.dsl_file), &mut aml)?;
+    }
+    builder.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// Write the DSDT as the C array `iasl -tc` produces, which the kernel
+/// builds in with CONFIG_ACPI_CUSTOM_DSDT_FILE, plus the config fragment
+/// pointing at it. Other tables cannot be built in and are left out.
+fn kernel_include(validation: &Validation, output: &Path, progress: &dyn Fn(&str)) -> Result<()> {
+    let mut dsdt = None;
+    for t in validation.tables.iter() {
+        if t.header.signature == "DSDT" {
+            dsdt = Some(t);
+        } else {
+            progress(&format!(
+                "{} left out, only the DSDT can be built into the kernel",
+                t.dsl_file
+            ));
+        }
+    }
+    let dsdt = match dsdt {
+        Some(dsdt) => dsdt,
+        None => bail!("the DSDT is not modified, nothing to build into the kernel"),
+    };
+
+    let hex = iasl::compile_c(
+        &dsdt.snapshot,
+        &workspace::staging_dir().join(format!("{}_c", apply::stem(&dsdt.dsl_file))),
+        &dsdt.dsl_file,
+    )?;
+    fs::create_dir_all(output)?;
+    let include = output.canonicalize()?.join("dsdt.hex");
+    fs::copy(hex, &include)?;
+    fs::write(
+        output.join("acpi_custom_dsdt.config"),
+        format!("CONFIG_ACPI_CUSTOM_DSDT_FILE=\"{}\"\n", include.display()),
+    )?;
+    Ok(())
+}
+
+/// Write the tables for a virtual machine and report the QEMU arguments
+/// adding them. QEMU installs such tables next to the ones it generates, so
+/// only tables it does not build itself, such as SSDTs, take effect as is.
+fn qemu_tables(validation: &Validation, output: &Path, progress: &dyn Fn(&str)) -> Result<()> {
+    aml_dir(validation, output)?;
+    let output = output.canonicalize()?;
+    let mut args = vec![];
+    for t in validation.tables.iter() {
+        if t.header.signature != "SSDT" {
+            progress(&format!(
+                "{} is a {}, QEMU adds it next to its own instead of replacing it",
+                t.dsl_file, t.header.signature
+            ));
+        }
+        args.push(format!(
+            "-acpitable file={}",
+            output.join(aml_name(&t.dsl_file)).display()
+        ));
+    }
+    progress("qemu arguments:");
+    progress(&args.join(" "));
+    Ok(())
+}
+
+/// Validate the modified tables and write them to `output` in `format`,
+/// without touching the boot configuration.
+pub fn export(
+    dsl_files: &[String],
+    format: Format,
+    output: &Path,
+    progress: &dyn Fn(&str),
+    cancel: &AtomicBool,
+) -> Result<PathBuf> {
+    if dsl_files.is_empty() {
+        bail!("no modified tables to export");
+    }
+    let validation = apply::validate_workspace(dsl_files, progress, cancel)?;
+    let written = match format {
+        Format::Cpio => archive(&validation, output),
+        Format::Aml => aml_dir(&validation, output),
+        Format::Tar => tarball(&validation, output),
+        Format::Kernel => kernel_include(&validation, output, progress),
+        Format::Qemu => qemu_tables(&validation, output, progress),
+    };
+    written.with_context(|| format!("fail to write {}", output.display()))?;
+    Ok(output.to_path_buf())
+}
+
+/// Export the modified tables of the workspace from the command line.
+pub fn run(format: Format, output: Option<PathBuf>) -> Result<()> {
+    let output = output.unwrap_or_else(|| PathBuf::from(format.default_output()));
+    let progress = |line: &str| println!("{}", line);
+    let cancel = AtomicBool::new(false);
+    let output = export(&workspace::modified()?, format, &output, &progress, &cancel)?;
+    println!("{} written to {}", format.describe(), output.display());
+    Ok(())
+}
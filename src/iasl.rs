@@ -0,0 +1,67 @@
+use crate::config;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run iasl and turn a failed run into an error carrying the compiler messages.
+pub fn run(args: &[&str], what: &str) -> Result<()> {
+    let output = Command::new(&config::get().tools.iasl)
+        .args(args)
+        .output()
+        .context("fail to execute iasl")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut messages: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+        .filter(|l| l.contains("Error"))
+        .map(|l| l.trim().to_string())
+        .collect();
+    if messages.is_empty() {
+        messages.push(format!("iasl exited with {}", output.status));
+    }
+    Err(anyhow!("{} failed: {}", what, messages.join("; ")))
+}
+
+pub fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("invalid path {}", path.display()))
+}
+
+/// Compile `source`, the AML is written to `<prefix>.aml`.
+pub fn compile(source: &Path, prefix: &Path, what: &str) -> Result<PathBuf> {
+    run(
+        &["-p", path_str(prefix)?, path_str(source)?],
+        &format!("{}: compile", what),
+    )?;
+    Ok(prefix.with_extension("aml"))
+}
+
+/// Compile `source` leaving references to objects it does not declare, error
+/// 6084, for the kernel to resolve when it loads the AML.
+pub fn compile_unresolved(source: &Path, prefix: &Path, what: &str) -> Result<PathBuf> {
+    run(
+        &["-vw", "6084", "-p", path_str(prefix)?, path_str(source)?],
+        &format!("{}: compile", what),
+    )?;
+    Ok(prefix.with_extension("aml"))
+}
+
+/// Compile `source` to a C array, written to `<prefix>.hex`.
+pub fn compile_c(source: &Path, prefix: &Path, what: &str) -> Result<PathBuf> {
+    run(
+        &["-tc", "-p", path_str(prefix)?, path_str(source)?],
+        &format!("{}: compile", what),
+    )?;
+    Ok(prefix.with_extension("hex"))
+}
+
+/// Disassemble `aml`, the source is written to `<prefix>.dsl`.
+pub fn disassemble(aml: &Path, prefix: &Path, what: &str) -> Result<PathBuf> {
+    run(
+        &["-p", path_str(prefix)?, "-d", path_str(aml)?],
+        &format!("{}: disassemble", what),
+    )?;
+    Ok(prefix.with_extension("dsl"))
+}
@@ -0,0 +1,332 @@
+use crate::apply::Validation;
+use crate::versions;
+use crate::workspace;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+/// A table installed by an apply.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Table {
+    pub dsl_file: String,
+    pub signature: String,
+    pub sha256: String,
+    pub oem_revision: u32,
+    pub origin_oem_revision: Option<u32>,
+}
+
+/// One apply, as a line of the journal.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub timestamp: String,
+    pub user: String,
+    /// `initrd` or `efivar`.
+    pub target: String,
+    pub boot_entry: String,
+    pub tables: Vec<Table>,
+    /// Sources of the tables as validated, kept in the history directory.
+    #[serde(skip)]
+    sources: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_initrd: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<PathBuf>,
+    /// Variant of the workspace applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Version of the workspace holding the applied sources.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Directory of the history holding the sources, named after the
+    /// timestamp with a suffix if another apply took that name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<String>,
+}
+
+/// JSON lines journal of the applies, kept across re-dumps.
+pub fn journal_file() -> PathBuf {
+    workspace::root().join("journal.jsonl")
+}
+
+/// Sources of the tables of each apply, by entry timestamp.
+pub fn history_dir() -> PathBuf {
+    workspace::root().join("history")
+}
+
+pub(crate) fn user() -> String {
+    env::var("SUDO_USER")
+        .or_else(|_| env::var("USER"))
+        .unwrap_or_else(|_| {
+            fs::metadata("/proc/self")
+                .map(|m| format!("uid {}", m.uid()))
+                .unwrap_or_default()
+        })
+}
+
+impl Entry {
+    pub fn new(target: &str, boot_entry: &str, validation: &Validation) -> Result<Self> {
+        let mut tables = vec![];
+        for t in validation.tables.iter() {
+            tables.push(Table {
+                dsl_file: t.dsl_file.clone(),
+                signature: t.header.signature.clone(),
+                sha256: workspace::sha256(&fs::read(&t.aml)?),
+                oem_revision: t.header.oem_revision,
+                origin_oem_revision: t.origin_oem_revision,
+            });
+        }
+        Ok(Self {
+            timestamp: Local::now().format("%Y-%m-%dT%H:%M:%S%z").to_string(),
+            user: user(),
+            target: target.to_string(),
+            boot_entry: boot_entry.to_string(),
+            tables,
+            sources: validation
+                .tables
+                .iter()
+                .map(|t| (t.dsl_file.clone(), t.source.clone()))
+                .collect(),
+            initrd: None,
+            previous_initrd: None,
+            variables: vec![],
+            variant: versions::variant().ok(),
+            version: None,
+            history: None,
+        })
+    }
+
+    fn sources_dir(&self) -> PathBuf {
+        match &self.history {
+            Some(name) => history_dir().join(name),
+            None => history_dir().join(self.timestamp.replace(':', "")),
+        }
+    }
+
+    /// Source of a table as it was applied.
+    pub fn source(&self, dsl_file: &str) -> Option<String> {
+        fs::read_to_string(self.sources_dir().join(dsl_file)).ok()
+    }
+
+    /// One line summary.
+    pub fn title(&self) -> String {
+        let tables: Vec<&str> = self.tables.iter().map(|t| t.dsl_file.as_str()).collect();
+        format!(
+            "{}  {}  {}  {}",
+            self.timestamp,
+            self.user,
+            self.target,
+            tables.join(" ")
+        )
+    }
+
+    /// Human readable description.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("time: {}", self.timestamp),
+            format!("user: {}", self.user),
+            format!("target: {}", self.target),
+            format!("boot entry: {}", self.boot_entry),
+        ];
+        if let Some(initrd) = &self.initrd {
+            lines.push(format!("initrd: {}", initrd.display()));
+        }
+        if let Some(previous) = &self.previous_initrd {
+            lines.push(format!("previous initrd: {}", previous.display()));
+        }
+        for variable in self.variables.iter() {
+            lines.push(format!("efi variable: {}", variable.display()));
+        }
+        if let Some(variant) = &self.variant {
+            lines.push(format!("variant: {}", variant));
+        }
+        if let Some(version) = &self.version {
+            lines.push(format!("workspace version: {}", version));
+        }
+        lines.push("tables:".to_string());
+        for t in self.tables.iter() {
+            let origin = t
+                .origin_oem_revision
+                .map_or(String::new(), |r| format!(" (firmware {:#x})", r));
+            lines.push(format!(
+                "  {:<12} {:<4} oem rev {:#x}{}  sha256 {}",
+                t.dsl_file, t.signature, t.oem_revision, origin, t.sha256
+            ));
+        }
+        lines
+    }
+}
+
+/// Create a directory of the history named `name`, or `name-2`, `name-3`...
+/// if applies within the same second took the name before.
+fn create_history_dir(name: &str) -> Result<PathBuf> {
+    fs::create_dir_all(history_dir())?;
+    for n in 1.. {
+        let dir = match n {
+            1 => history_dir().join(name),
+            _ => history_dir().join(format!("{}-{}", name, n)),
+        };
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("fail to create {}", dir.display())),
+        }
+    }
+    unreachable!()
+}
+
+/// Append an apply to the journal and keep the sources it installed, also as
+/// a version of the workspace if the tables still hold them.
+pub fn record(mut entry: Entry) -> Result<()> {
+    let unchanged = entry.sources.iter().all(|(dsl_file, source)| {
+        fs::read_to_string(workspace::modified_dir().join(dsl_file)).is_ok_and(|s| s == *source)
+    });
+    if unchanged {
+        let message = format!("{} apply to {}", entry.target, entry.boot_entry);
+        entry.version = versions::checkpoint(&message)
+            .and_then(|_| versions::current())
+            .ok();
+    }
+
+    let dir = create_history_dir(&entry.timestamp.replace(':', ""))?;
+    entry.history = Some(dir.file_name().unwrap().to_string_lossy().to_string());
+    for (dsl_file, source) in entry.sources.iter() {
+        fs::write(dir.join(dsl_file), source)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_file())?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Applies recorded in the journal, oldest first.
+pub fn entries() -> Result<Vec<Entry>> {
+    let path = journal_file();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => return Ok(vec![]),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}:{}: invalid entry", path.display(), index + 1))
+        })
+        .collect()
+}
+
+/// Differences between two applies: tables added, dropped and changed, with
+/// the changes to their sources. Without `old` the sources are compared with
+/// the firmware originals.
+pub fn diff(old: Option<&Entry>, new: &Entry) -> Vec<String> {
+    let mut lines = vec![];
+    let mut dsl_files: Vec<&str> = new.tables.iter().map(|t| t.dsl_file.as_str()).collect();
+    if let Some(old) = old {
+        for t in old.tables.iter() {
+            if !dsl_files.contains(&t.dsl_file.as_str()) {
+                dsl_files.push(&t.dsl_file);
+            }
+        }
+    }
+    dsl_files.sort();
+
+    for dsl_file in dsl_files {
+        let find = |entry: &Entry| {
+            entry
+                .tables
+                .iter()
+                .find(|t| t.dsl_file == dsl_file)
+                .cloned()
+        };
+        let before = old.and_then(find);
+        let after = find(new);
+        match (&before, &after) {
+            (None, Some(_)) if old.is_some() => lines.push(format!("{}: added", dsl_file)),
+            (Some(_), None) => {
+                lines.push(format!("{}: no longer applied", dsl_file));
+                continue;
+            }
+            (Some(b), Some(a)) if b.sha256 == a.sha256 => {
+                lines.push(format!("{}: unchanged", dsl_file));
+                continue;
+            }
+            (Some(b), Some(a)) => lines.push(format!(
+                "{}: oem rev {:#x} -> {:#x}",
+                dsl_file, b.oem_revision, a.oem_revision
+            )),
+            _ => {}
+        }
+
+        let before_source = match old {
+            Some(old) => old.source(dsl_file),
+            None => fs::read_to_string(workspace::origin_dir().join(dsl_file)).ok(),
+        }
+        .unwrap_or_default();
+        let after_source = new.source(dsl_file).unwrap_or_default();
+        let diff = TextDiff::from_lines(&before_source, &after_source);
+        let (old_name, new_name) = match old {
+            Some(old) => (old.timestamp.clone(), new.timestamp.clone()),
+            None => ("firmware".to_string(), new.timestamp.clone()),
+        };
+        let unified = diff
+            .unified_diff()
+            .header(
+                &format!("{} ({})", dsl_file, old_name),
+                &format!("{} ({})", dsl_file, new_name),
+            )
+            .to_string();
+        lines.extend(unified.lines().map(String::from));
+    }
+    lines
+}
+
+/// List the applies, or show one and what it changed, from the command line.
+/// Entries are numbered from 1, oldest first.
+pub fn run(entry: Option<usize>, other: Option<usize>) -> Result<()> {
+    let entries = entries()?;
+    let get = |n: usize| -> Result<&Entry> {
+        match n.checked_sub(1).and_then(|i| entries.get(i)) {
+            Some(entry) => Ok(entry),
+            None => bail!("no apply #{}, {} recorded", n, entries.len()),
+        }
+    };
+
+    let n = match entry {
+        Some(n) => n,
+        None => {
+            if entries.is_empty() {
+                println!("no applies recorded");
+            }
+            for (index, entry) in entries.iter().enumerate() {
+                println!("#{:<4}{}", index + 1, entry.title());
+            }
+            return Ok(());
+        }
+    };
+
+    let new = get(n)?;
+    let old = match other {
+        Some(m) => Some(get(m)?),
+        None => n.checked_sub(2).and_then(|i| entries.get(i)),
+    };
+    for line in new.describe() {
+        println!("{}", line);
+    }
+    println!();
+    for line in diff(old, new) {
+        println!("{}", line);
+    }
+    Ok(())
+}
@@ -1,7 +1,79 @@
+pub mod apply;
+pub mod bootloader;
+pub mod cmdline;
+pub mod config;
+pub mod cpio;
+pub mod diff;
+pub mod dsl;
+pub mod efivar;
+pub mod export;
+pub mod iasl;
+pub mod journal;
+pub mod patch;
+pub mod runtime;
+pub mod table;
 pub mod term;
+pub mod verify;
+pub mod versions;
 pub mod web;
+pub mod workspace;
 
-use clap::{value_parser, Arg, Command};
+use clap::{value_parser, Arg, ArgAction, Command};
+use config::Config;
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
+
+fn check_executable(executable: &Path) {
+    let output = process::Command::new("which")
+        .arg(executable)
+        .output()
+        .unwrap_or_else(|_| panic!("fail to check {}!", executable.display()));
+    if !output.status.success() {
+        eprintln!("{} not found!", executable.display());
+        exit(1);
+    }
+}
+
+fn check_user() {
+    let output = process::Command::new("id")
+        .arg("-u")
+        .output()
+        .expect("fail to check user!");
+    if !output.status.success() {
+        eprintln!("check user failed");
+    } else {
+        if String::from_utf8(output.stdout.to_owned())
+            .unwrap()
+            .trim_end()
+            != "0"
+        {
+            eprintln!("acpied must be run as root!");
+            exit(1);
+        }
+    }
+}
+
+pub(crate) fn check_prerequisites(redump: bool) {
+    let config = config::get();
+    let tools = &config.tools;
+    // imported tables and a tree mounted elsewhere are handled without grubby
+    // or acpidump, unless the firmware is to be dumped in place of the import
+    if config.is_live() && (redump || !workspace::is_offline()) {
+        check_user();
+        check_executable(&tools.grubby);
+        check_executable(&tools.acpidump);
+        check_executable(&tools.acpixtract);
+    }
+    check_executable(&tools.iasl);
+}
+
+fn path_arg(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_parser(value_parser!(PathBuf))
+        .global(true)
+        .help(help)
+}
 
 pub fn run() -> anyhow::Result<()> {
     let args = Command::new("ACPI Editor")
@@ -22,12 +94,428 @@ pub fn run() -> anyhow::Result<()> {
                 .long("port")
                 .default_value("8000"),
         )
+        .arg(
+            Arg::new("redump")
+                .long("redump")
+                .action(ArgAction::SetTrue)
+                .help("Dump the firmware tables again instead of reusing the workspace"),
+        )
+        .arg(path_arg(
+            "config",
+            "Read this config file after the default ones",
+        ))
+        .arg(path_arg(
+            "root",
+            "Resolve system paths under this directory",
+        ))
+        .arg(path_arg("workspace", "Workspace root directory"))
+        .arg(path_arg("log", "Log file"))
+        .arg(path_arg(
+            "boot-dir",
+            "Directory the new initrd is written to",
+        ))
+        .arg(path_arg("iasl", "Path to iasl"))
+        .arg(path_arg("acpidump", "Path to acpidump"))
+        .arg(path_arg("acpixtract", "Path to acpixtract"))
+        .arg(path_arg("grubby", "Path to grubby"))
+        .arg(path_arg("git", "Path to git"))
+        .arg(path_arg("patch", "Path to patch"))
+        .arg(path_arg("init-script", "Path to the acpied-init script"))
+        .subcommand(
+            Command::new("open")
+                .about("Edit tables from an acpidump file or a directory of .dat/.aml files")
+                .arg(
+                    Arg::new("source")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Write the modified tables to a file without touching the boot config")
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .value_parser(export::Format::ALL.map(|f| f.name()))
+                        .default_value("cpio")
+                        .help("cpio archive to prepend to an initrd, .aml files in a directory or tarball, the DSDT as a C include for building into the kernel, or .aml files for QEMU -acpitable"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Output path, named after the format by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the changes to tables against the firmware originals")
+                .arg(
+                    Arg::new("table")
+                        .action(ArgAction::Append)
+                        .help("Table names, e.g. ssdt1, all modified tables if none"),
+                )
+                .arg(
+                    Arg::new("side-by-side")
+                        .short('y')
+                        .long("side-by-side")
+                        .value_name("WIDTH")
+                        .value_parser(value_parser!(usize))
+                        .num_args(0..=1)
+                        .default_missing_value("160")
+                        .help("Firmware and edited source side by side, in WIDTH columns"),
+                ),
+        )
+        .subcommand(
+            Command::new("patch")
+                .about("Share the changes to the tables as a unified diff")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Write a unified diff of all modified tables against the firmware originals")
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_parser(value_parser!(PathBuf))
+                                .help("Patch file, printed if not given"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Apply a unified diff to the tables of the workspace")
+                        .arg(
+                            Arg::new("file")
+                                .value_parser(value_parser!(PathBuf))
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("fuzz")
+                                .long("fuzz")
+                                .value_parser(value_parser!(usize))
+                                .default_value("2")
+                                .help("Lines of context a hunk may ignore to apply"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Report how the hunks apply without changing anything"),
+                        )
+                        .arg(
+                            Arg::new("redump")
+                                .long("redump")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("dry-run")
+                                .help("Dump the firmware tables again first, edits are moved aside"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("revert")
+                .about("Undo all edits to tables, bringing back the firmware originals")
+                .arg(
+                    Arg::new("table")
+                        .action(ArgAction::Append)
+                        .required(true)
+                        .help("Table names, e.g. ssdt1"),
+                ),
+        )
+        .subcommand(
+            Command::new("load")
+                .about("Load a modified or new SSDT into the running kernel through configfs")
+                .arg(Arg::new("table").required(true).help("Table name, e.g. ssdt1")),
+        )
+        .subcommand(
+            Command::new("unload")
+                .about("Unload an SSDT loaded with acpied load")
+                .arg(Arg::new("table").required(true).help("Table name, e.g. ssdt1")),
+        )
+        .subcommand(
+            Command::new("cmdline")
+                .about("Show or change the ACPI kernel parameters of a boot entry")
+                .arg(
+                    Arg::new("kernel")
+                        .long("kernel")
+                        .help("Kernel of the boot entry, the default entry if not given"),
+                )
+                .arg(
+                    Arg::new("set")
+                        .long("set")
+                        .action(ArgAction::Append)
+                        .value_name("KEY=VALUE")
+                        .help("Replace all values of a parameter, repeat for several values"),
+                )
+                .arg(
+                    Arg::new("unset")
+                        .long("unset")
+                        .action(ArgAction::Append)
+                        .value_name("KEY")
+                        .help("Remove a parameter"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that the tables of the last apply are in effect after a reboot")
+                .arg(
+                    Arg::new("dmesg")
+                        .long("dmesg")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Kernel log to check, such as saved dmesg or journalctl -k output"),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("List past applies, or show one and what it changed")
+                .arg(
+                    Arg::new("entry")
+                        .value_parser(value_parser!(usize))
+                        .help("Apply to show, numbered as listed"),
+                )
+                .arg(
+                    Arg::new("other")
+                        .value_parser(value_parser!(usize))
+                        .help("Apply to compare with, the one before by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("versions")
+                .about("List the versions of the modified tables, or show one")
+                .arg(Arg::new("id").help("Version to show"))
+                .arg(
+                    Arg::new("table")
+                        .long("table")
+                        .help("Only versions changing this table, e.g. ssdt1.dsl"),
+                ),
+        )
+        .subcommand(
+            Command::new("checkpoint")
+                .about("Record the modified tables as a new version")
+                .arg(Arg::new("message").help("Name of the version")),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Bring the modified tables back to an earlier version")
+                .arg(Arg::new("id").required(true).help("Version to return to")),
+        )
+        .subcommand(
+            Command::new("variant")
+                .about("List the variants of the modified tables, or create, switch to or delete one")
+                .subcommand(
+                    Command::new("new")
+                        .about("Create a variant from the current tables and switch to it")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("from-firmware")
+                                .long("from-firmware")
+                                .action(ArgAction::SetTrue)
+                                .help("Start from the firmware tables instead"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("switch")
+                        .about("Switch to a variant, the current tables stay in theirs")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete a variant")
+                        .arg(Arg::new("name").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("apply")
+                .about("Apply the modified tables of the workspace")
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Print what would be done without changing anything"),
+                )
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .value_parser(["initrd", "efivar"])
+                        .default_value("initrd")
+                        .help("Prepend the tables to the initrd, or load SSDTs from EFI variables"),
+                )
+                .arg(
+                    Arg::new("variant")
+                        .long("variant")
+                        // switching changes the tables, which a dry run must not
+                        .conflicts_with("dry-run")
+                        .help("Switch to this variant of the tables first"),
+                ),
+        )
         .get_matches();
 
+    config::init(Config::load(&args)?)?;
+
+    match args.subcommand() {
+        Some(("open", sub)) => {
+            let tools = &config::get().tools;
+            check_executable(&tools.iasl);
+            check_executable(&tools.acpixtract);
+            let source = sub.get_one::<PathBuf>("source").unwrap();
+            workspace::import(source, args.get_flag("redump"), &|line| {
+                println!("{}", line)
+            })?;
+            return term::run(false);
+        }
+        Some(("export", sub)) => {
+            check_executable(&config::get().tools.iasl);
+            let format = sub.get_one::<String>("format").unwrap();
+            return export::run(
+                export::Format::from_name(format).unwrap(),
+                sub.get_one::<PathBuf>("output").cloned(),
+            );
+        }
+        Some(("diff", sub)) => {
+            let tables: Vec<String> = sub
+                .get_many::<String>("table")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            return diff::run(&tables, sub.get_one::<usize>("side-by-side").copied());
+        }
+        Some(("patch", sub)) => match sub.subcommand() {
+            Some(("export", sub)) => return patch::run_export(sub.get_one::<PathBuf>("output")),
+            Some(("import", sub)) => {
+                check_prerequisites(sub.get_flag("redump"));
+                check_executable(&config::get().tools.patch);
+                return patch::run_import(
+                    sub.get_one::<PathBuf>("file").unwrap(),
+                    *sub.get_one::<usize>("fuzz").unwrap(),
+                    sub.get_flag("dry-run"),
+                    sub.get_flag("redump"),
+                );
+            }
+            _ => {}
+        },
+        Some(("revert", sub)) => {
+            for table in sub.get_many::<String>("table").unwrap() {
+                let dsl_file = format!("{}.dsl", apply::stem(table));
+                if workspace::revert(&dsl_file)? {
+                    println!("{} reverted to the firmware original", dsl_file);
+                } else {
+                    println!("{} removed, the firmware has no such table", dsl_file);
+                }
+            }
+            return Ok(());
+        }
+        Some((command @ ("load" | "unload"), sub)) => {
+            check_executable(&config::get().tools.iasl);
+            let table = sub.get_one::<String>("table").unwrap();
+            return runtime::run(table, command == "unload");
+        }
+        Some(("cmdline", sub)) => {
+            let values = |name: &str| -> Vec<String> {
+                sub.get_many::<String>(name)
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect()
+            };
+            return cmdline::run(
+                sub.get_one::<String>("kernel"),
+                &values("set"),
+                &values("unset"),
+            );
+        }
+        Some(("verify", sub)) => return verify::run(sub.get_one::<PathBuf>("dmesg")),
+        Some(("history", sub)) => {
+            return journal::run(
+                sub.get_one::<usize>("entry").copied(),
+                sub.get_one::<usize>("other").copied(),
+            )
+        }
+        Some(("versions", sub)) => {
+            versions::init(&|line| println!("{}", line))?;
+            let table = sub.get_one::<String>("table").map(|t| t.as_str());
+            match sub.get_one::<String>("id") {
+                Some(id) => {
+                    for line in versions::show(id, table)? {
+                        println!("{}", line);
+                    }
+                }
+                None => {
+                    for version in versions::log(table)? {
+                        println!("{}", version.title());
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(("checkpoint", sub)) => {
+            versions::init(&|line| println!("{}", line))?;
+            let message = sub
+                .get_one::<String>("message")
+                .map_or("checkpoint", |m| m.as_str());
+            match versions::checkpoint(message)? {
+                Some(id) => println!("{} {}", id, message),
+                None => println!("no changes since the last version"),
+            }
+            return Ok(());
+        }
+        Some(("restore", sub)) => {
+            versions::init(&|line| println!("{}", line))?;
+            let id = sub.get_one::<String>("id").unwrap();
+            match versions::restore(id)? {
+                Some(new) => println!("{} return to {}", new, id),
+                None => println!("tables already at {}", id),
+            }
+            return Ok(());
+        }
+        Some(("variant", sub)) => {
+            versions::init(&|line| println!("{}", line))?;
+            match sub.subcommand() {
+                Some(("new", sub)) => {
+                    let name = sub.get_one::<String>("name").unwrap();
+                    versions::new_variant(name, sub.get_flag("from-firmware"))?;
+                    println!("switched to new variant {}", name);
+                }
+                Some(("switch", sub)) => {
+                    let name = sub.get_one::<String>("name").unwrap();
+                    versions::switch_variant(name)?;
+                    println!("switched to variant {}", name);
+                }
+                Some(("delete", sub)) => {
+                    let name = sub.get_one::<String>("name").unwrap();
+                    versions::delete_variant(name)?;
+                    println!("variant {} deleted", name);
+                }
+                _ => {
+                    let current = versions::variant()?;
+                    for name in versions::variants()? {
+                        let mark = if name == current { "*" } else { " " };
+                        let tables = versions::variant_tables(&name)?;
+                        println!("{} {:<24}{}", mark, name, tables.join(" "));
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(("apply", sub)) => {
+            check_prerequisites(false);
+            if let Some(variant) = sub.get_one::<String>("variant") {
+                versions::init(&|line| println!("{}", line))?;
+                versions::switch_variant(variant)?;
+                println!("switched to variant {}", variant);
+            }
+            let dry_run = sub.get_flag("dry-run");
+            if sub.get_one::<String>("target").unwrap() == "efivar" {
+                return efivar::run(dry_run);
+            }
+            return apply::run(dry_run);
+        }
+        _ => {}
+    }
+
     let mode = args.get_one::<String>("mode").unwrap();
 
     match mode.as_str() {
-        "term" => term::run(),
+        "term" => term::run(args.get_flag("redump")),
         "web" => web::run(),
         _ => {
             // let port = args.get_one::<usize>("port").unwrap();
@@ -0,0 +1,137 @@
+use crate::config;
+use crate::diff;
+use crate::versions;
+use crate::workspace;
+use anyhow::{bail, Context, Result};
+use similar::TextDiff;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Lines of context around each change, as `diff -u`.
+const CONTEXT: usize = 3;
+
+/// Hunks the last import could not apply, in the workspace.
+pub fn reject_file() -> PathBuf {
+    workspace::root().join("patch.rej")
+}
+
+/// Unified diff of all modified tables against the firmware originals, to be
+/// applied with `patch -p1` in another workspace.
+pub fn export() -> Result<String> {
+    let mut patch = String::new();
+    for dsl_file in workspace::modified()? {
+        let (origin, modified) = diff::sources(&dsl_file)?;
+        let diff = TextDiff::from_lines(&origin, &modified);
+        patch.push_str(
+            &diff
+                .unified_diff()
+                .context_radius(CONTEXT)
+                .header(
+                    &format!("origin/{}", dsl_file),
+                    &format!("modified/{}", dsl_file),
+                )
+                .to_string(),
+        );
+    }
+    Ok(patch)
+}
+
+/// Apply a unified diff to the modified tables, letting each hunk match up to
+/// `fuzz` lines of context off. Returns the patch output, reporting offsets,
+/// fuzz and failed hunks, and whether all hunks applied.
+pub fn import(file: &Path, fuzz: usize, dry_run: bool) -> Result<(Vec<String>, bool)> {
+    let rejects = reject_file();
+    let _ = fs::remove_file(&rejects);
+    let mut command = Command::new(&config::get().tools.patch);
+    command
+        .arg("-p1")
+        .arg("--forward")
+        .arg("--batch")
+        .arg("--no-backup-if-mismatch")
+        .arg(format!("--fuzz={}", fuzz))
+        .arg("--reject-file")
+        .arg(&rejects)
+        .arg("--directory")
+        .arg(workspace::modified_dir())
+        .arg("--input")
+        .arg(file);
+    if dry_run {
+        command.arg("--dry-run");
+    }
+    let output = command.output().context("fail to execute patch")?;
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // 1 when hunks failed, more when the patch could not be read at all
+    match output.status.code() {
+        Some(0) => Ok((lines, true)),
+        Some(1) => {
+            lines.extend(stderr.lines().map(String::from));
+            Ok((lines, false))
+        }
+        _ => bail!(
+            "patch failed: {}",
+            stderr.trim().lines().last().unwrap_or_default()
+        ),
+    }
+}
+
+/// Write the patch of the modified tables to `output`, or print it.
+pub fn run_export(output: Option<&PathBuf>) -> Result<()> {
+    let patch = export()?;
+    match output {
+        Some(path) => {
+            fs::write(path, &patch).with_context(|| format!("fail to write {}", path.display()))?;
+            let tables = patch.lines().filter(|l| l.starts_with("+++ ")).count();
+            println!("{} tables written to {}", tables, path.display());
+        }
+        None => print!("{}", patch),
+    }
+    Ok(())
+}
+
+/// Apply a patch to the workspace from the command line, dumping the tables
+/// first if there is no workspace yet or `redump` is set. The tables are kept
+/// as a version before and after. A dry run leaves the workspace as it is and
+/// needs one to exist.
+pub fn run_import(file: &Path, fuzz: usize, dry_run: bool, redump: bool) -> Result<()> {
+    let progress = |line: &str| println!("{}", line);
+    if !file.is_file() {
+        bail!("no patch {}", file.display());
+    }
+    let file = file
+        .canonicalize()
+        .with_context(|| format!("fail to open {}", file.display()))?;
+    if !dry_run {
+        workspace::open(redump, &progress)?;
+    } else if !workspace::modified_dir().is_dir() {
+        bail!(
+            "no workspace in {}, a dry run does not dump the tables",
+            workspace::root().display()
+        );
+    }
+
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    if !dry_run {
+        let _ = versions::checkpoint(&format!("before importing {}", name));
+    }
+    let (lines, applied) = import(&file, fuzz, dry_run)?;
+    for line in lines.iter() {
+        println!("{}", line);
+    }
+    if !dry_run {
+        if let Ok(Some(id)) = versions::checkpoint(&format!("import {}", name)) {
+            println!("{} import {}", id, name);
+        }
+    }
+    if !applied {
+        if dry_run {
+            bail!("some hunks would be rejected");
+        }
+        bail!("some hunks rejected, saved to {}", reject_file().display());
+    }
+    Ok(())
+}
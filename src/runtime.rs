@@ -0,0 +1,217 @@
+use crate::apply;
+use crate::config;
+use crate::dsl;
+use crate::iasl;
+use crate::table;
+use crate::workspace;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+/// Where the kernel accepts SSDTs at runtime, see CONFIG_ACPI_CONFIGFS.
+const CONFIGFS_TABLES: &str = "/sys/kernel/config/acpi/table";
+
+/// Where the kernel replaces a single control method, see
+/// CONFIG_ACPI_CUSTOM_METHOD.
+const CUSTOM_METHOD: &str = "/sys/kernel/debug/acpi/custom_method";
+
+/// AML opcode of a Method declaration.
+const METHOD_OP: u8 = 0x14;
+
+fn configfs_dir() -> PathBuf {
+    config::get().system_path(CONFIGFS_TABLES)
+}
+
+fn table_dir(dsl_file: &str) -> PathBuf {
+    configfs_dir().join(apply::stem(dsl_file))
+}
+
+/// Compile an SSDT and load it into the running kernel through configfs.
+/// Returns the configfs item holding the table.
+pub fn load(dsl_file: &str, progress: &dyn Fn(&str), cancel: &AtomicBool) -> Result<PathBuf> {
+    let configfs = configfs_dir();
+    if !configfs.is_dir() {
+        bail!(
+            "{} not found, is configfs mounted and CONFIG_ACPI_CONFIGFS enabled?",
+            configfs.display()
+        );
+    }
+    let dir = table_dir(dsl_file);
+    if dir.exists() {
+        bail!("{} is already loaded, unload it first", dsl_file);
+    }
+
+    let validation = apply::validate_workspace(&[dsl_file.to_string()], progress, cancel)?;
+    let table = &validation.tables[0];
+    if table.header.signature != "SSDT" {
+        bail!(
+            "{} is a {}, only SSDTs can be loaded at runtime",
+            dsl_file,
+            table.header.signature
+        );
+    }
+
+    fs::create_dir(&dir).with_context(|| format!("fail to create {}", dir.display()))?;
+    let aml = fs::read(&table.aml)?;
+    if let Err(e) = fs::write(dir.join("aml"), aml) {
+        let _ = fs::remove_dir(&dir);
+        return Err(e).with_context(|| format!("kernel refused {}", dsl_file));
+    }
+    Ok(dir)
+}
+
+/// Unload an SSDT loaded by `load`.
+pub fn unload(dsl_file: &str) -> Result<()> {
+    let dir = table_dir(dsl_file);
+    if !dir.exists() {
+        bail!("{} is not loaded", dsl_file);
+    }
+    // configfs drops the table on rmdir, a stand-in directory still holds
+    // the aml file
+    if config::get().is_live() {
+        fs::remove_dir(&dir)
+    } else {
+        fs::remove_dir_all(&dir)
+    }
+    .with_context(|| format!("fail to remove {}", dir.display()))
+}
+
+/// Build a table holding only the control method around `line`, declared by
+/// its absolute path so the kernel replaces the existing method. The kernel
+/// takes the method as the first and only opcode of the table, so references
+/// to other objects are not declared External but resolved on load.
+/// Returns the method path and the table source.
+fn method_table(lines: &[String], line: usize) -> Result<(String, String)> {
+    let objects = dsl::scan(lines);
+    let method =
+        dsl::method_at(&objects, line).ok_or_else(|| anyhow!("no method under the cursor"))?;
+
+    let header = &lines[method.start];
+    let (before, after) = header
+        .split_once("Method (")
+        .ok_or_else(|| anyhow!("unexpected method header: {}", header.trim()))?;
+    let args = after.find([',', ')']).map_or("", |index| &after[index..]);
+    let mut table = vec![
+        "DefinitionBlock (\"\", \"SSDT\", 2, \"ACPIED\", \"METHOD\", 0x00000001)".to_string(),
+        "{".to_string(),
+        format!("{}Method ({}{}", before, method.path, args),
+    ];
+    table.extend(lines[method.start + 1..=method.end].iter().cloned());
+    table.push("}".to_string());
+    Ok((method.path.clone(), table.join("\n") + "\n"))
+}
+
+/// Replace the control method around `line` of a table source in the running
+/// kernel through debugfs. Returns the method path and the AML size.
+pub fn custom_method(lines: &[String], line: usize) -> Result<(String, usize)> {
+    let target = config::get().system_path(CUSTOM_METHOD);
+    if !target.exists() {
+        bail!(
+            "{} not found, is debugfs mounted and CONFIG_ACPI_CUSTOM_METHOD enabled?",
+            target.display()
+        );
+    }
+    let (path, source) = method_table(lines, line)?;
+
+    let staging = workspace::staging_dir();
+    fs::create_dir_all(&staging)?;
+    let dsl_file = staging.join("custom_method.dsl");
+    fs::write(&dsl_file, source)?;
+    let aml = iasl::compile_unresolved(&dsl_file, &staging.join("custom_method"), &path)?;
+    let data = fs::read(&aml).with_context(|| format!("{}: no AML produced", path))?;
+    table::verify(&data).with_context(|| format!("{}: bad AML", path))?;
+    if data.get(table::HEADER_SIZE) != Some(&METHOD_OP) {
+        bail!("{}: the table does not start with the method", path);
+    }
+
+    fs::write(&target, &data).with_context(|| format!("kernel refused {}", path))?;
+    Ok((path, data.len()))
+}
+
+/// Load or unload a table from the command line.
+pub fn run(table: &str, unload: bool) -> Result<()> {
+    let dsl_file = format!("{}.dsl", apply::stem(table));
+    let dsl_file = dsl_file.as_str();
+    if unload {
+        self::unload(dsl_file)?;
+        println!("{} unloaded", dsl_file);
+        return Ok(());
+    }
+    let progress = |line: &str| println!("{}", line);
+    let cancel = AtomicBool::new(false);
+    let dir = load(dsl_file, &progress, &cancel)?;
+    println!("{} loaded as {}", dsl_file, dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"DefinitionBlock ("", "DSDT", 2, "OEMID", "TABLEID", 0x00000001)
+{
+    External (\_SB.ECOK, IntObj)
+    Scope (_SB)
+    {
+        Device (PCI0)
+        {
+            Name (FLAG, One)
+            Method (_DSM, 4, Serialized)  // _DSM: Device-Specific Method
+            {
+                If ((Arg0 == Zero)) { Return (FLAG) }
+                Return (\_SB.ECOK)
+            }
+        }
+    }
+}"#;
+
+    fn lines() -> Vec<String> {
+        SOURCE.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn method_by_absolute_path() {
+        let (path, table) = method_table(&lines(), 10).unwrap();
+        assert_eq!(path, "\\_SB.PCI0._DSM");
+        let expected = r#"DefinitionBlock ("", "SSDT", 2, "ACPIED", "METHOD", 0x00000001)
+{
+            Method (\_SB.PCI0._DSM, 4, Serialized)  // _DSM: Device-Specific Method
+            {
+                If ((Arg0 == Zero)) { Return (FLAG) }
+                Return (\_SB.ECOK)
+            }
+}
+"#;
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn method_header_and_body_lines() {
+        let lines = lines();
+        assert_eq!(
+            method_table(&lines, 8).unwrap(),
+            method_table(&lines, 12).unwrap()
+        );
+        assert!(method_table(&lines, 7).is_err());
+        assert!(method_table(&lines, 13).is_err());
+    }
+
+    #[test]
+    fn method_without_arguments() {
+        let lines: Vec<String> = [
+            "Scope (_SB)",
+            "{",
+            "    Method (XINI)",
+            "    {",
+            "    }",
+            "}",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let (path, table) = method_table(&lines, 3).unwrap();
+        assert_eq!(path, "\\_SB.XINI");
+        assert!(table.contains("\n    Method (\\_SB.XINI)\n    {\n    }\n}\n"));
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::Path;
+
+pub const HEADER_SIZE: usize = 36;
+
+/// Standard header shared by all ACPI system description tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableHeader {
+    pub signature: String,
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: String,
+    pub oem_table_id: String,
+    pub oem_revision: u32,
+    pub creator_id: String,
+    pub creator_revision: u32,
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\0', ' '])
+        .to_string()
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl TableHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE {
+            bail!("table too short: {} bytes", data.len());
+        }
+        Ok(Self {
+            signature: ascii(&data[0..4]),
+            length: le32(&data[4..8]),
+            revision: data[8],
+            checksum: data[9],
+            oem_id: ascii(&data[10..16]),
+            oem_table_id: ascii(&data[16..24]),
+            oem_revision: le32(&data[24..28]),
+            creator_id: ascii(&data[28..32]),
+            creator_revision: le32(&data[32..36]),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+}
+
+/// Check that the table is complete and its bytes sum up to zero.
+pub fn verify(data: &[u8]) -> Result<TableHeader> {
+    let header = TableHeader::parse(data)?;
+    if header.length as usize != data.len() {
+        bail!(
+            "{}: header length {} does not match size {}",
+            header.signature,
+            header.length,
+            data.len()
+        );
+    }
+    let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if sum != 0 {
+        bail!("{}: invalid checksum", header.signature);
+    }
+    Ok(header)
+}
@@ -1,28 +1,42 @@
+use crate::apply::{self, Installed, Plan};
+use crate::bootloader;
+use crate::cmdline;
+use crate::config;
+use crate::diff::{self, Diff, Marker, Tag};
+use crate::efivar;
+use crate::export::{self, Format};
+use crate::journal;
+use crate::runtime;
+use crate::versions::{self, Version};
+use crate::workspace::{self, Workspace};
 use anyhow::Result;
 use chrono::{Datelike, Timelike, Utc};
 use crossterm::event::{self, Event, KeyCode, KeyEvent};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use file_diff;
 use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
 use std::path::PathBuf;
-use std::process::{exit, Command};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::Spans,
+    text::{Span, Spans},
     widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
 use tui_textarea::{CursorMove, Input, Key, Scrolling, TextArea};
 
 const MAX_HISTORY_SIZE: usize = 100;
-const ORIGINDIR: &str = "/tmp/acpidump/origin";
-const MODIFIEDDIR: &str = "/tmp/acpidump/modified";
-const LOGFILE: &str = "/var/log/acpied.log";
+const TICK: Duration = Duration::from_millis(100);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
 
 struct StatefulList<T> {
     state: ListState,
@@ -70,93 +84,714 @@ enum Mode {
     Normal,
     Insert,
     Search,
+    Confirm,
+    Cmdline,
+    History,
+    Versions,
+    Variants,
+    Diff,
+}
+
+/// Panel the keys go to in normal mode, cycled with Tab.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Content,
+    Files,
+    Modified,
+}
+
+/// Messages sent from a worker thread back to the event loop.
+enum Message {
+    Log(String),
+    Initialized(Result<Workspace>),
+    Planned {
+        plan: Result<Plan>,
+        dry_run: bool,
+    },
+    Installed(Result<Installed>),
+    EfiVarPlanned(Result<efivar::Plan>),
+    EfiVarInstalled(Result<String>),
+    Exported(Format, Result<PathBuf>),
+    Loaded(String, Result<PathBuf>),
+    MethodReplaced(Result<(String, usize)>),
+    /// Commands run and the parameters of the boot entry after the update.
+    CmdlineUpdated(&'static str, Result<(Vec<String>, Vec<Vec<String>>)>),
+}
+
+/// Action waiting for the user to confirm it in a dialog.
+enum Pending {
+    Apply(Plan),
+    DryRun(Plan),
+    ApplyEfiVar(efivar::Plan),
+    Redump,
+    Export,
+    Restore(String),
+    DeleteVariant(String),
+    Revert(String),
+}
+
+impl Pending {
+    fn title(&self) -> &'static str {
+        match self {
+            Pending::Apply(_) => "APPLY",
+            Pending::DryRun(_) => "DRY RUN",
+            Pending::ApplyEfiVar(_) => "APPLY (EFI VARIABLES)",
+            Pending::Redump => "RE-DUMP",
+            Pending::Export => "EXPORT",
+            Pending::Restore(_) => "RESTORE",
+            Pending::DeleteVariant(_) => "DELETE VARIANT",
+            Pending::Revert(_) => "REVERT",
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = match self {
+            Pending::Apply(plan) | Pending::DryRun(plan) => plan.describe(),
+            Pending::ApplyEfiVar(plan) => plan.describe(),
+            Pending::Redump if workspace::is_offline() => vec![
+                "import the tables again?".to_string(),
+                "the current workspace is moved aside.".to_string(),
+            ],
+            Pending::Redump => vec![
+                "dump the firmware tables again?".to_string(),
+                "the current workspace is moved aside.".to_string(),
+            ],
+            Pending::Export => Format::ALL
+                .iter()
+                .map(|f| format!("({}) {}", f.key(), f.describe()))
+                .collect(),
+            Pending::Restore(id) => vec![
+                format!("bring the modified tables back to {}?", id),
+                "the current tables are kept as a version.".to_string(),
+            ],
+            Pending::DeleteVariant(name) => {
+                vec![format!("delete variant {} and all its versions?", name)]
+            }
+            Pending::Revert(dsl_file) if !workspace::origin_dir().join(dsl_file).exists() => {
+                vec![
+                    format!("remove {}?", dsl_file),
+                    "the firmware has no such table.".to_string(),
+                ]
+            }
+            Pending::Revert(dsl_file) => vec![
+                format!("undo all edits to {}?", dsl_file),
+                "the firmware original is restored.".to_string(),
+            ],
+        };
+        lines.push(String::new());
+        lines.push(match self {
+            Pending::Apply(_) | Pending::ApplyEfiVar(_) => {
+                "install these tables? (y/n)".to_string()
+            }
+            Pending::DryRun(_) => "dry run, nothing was changed (Esc to close)".to_string(),
+            Pending::Redump
+            | Pending::Restore(_)
+            | Pending::DeleteVariant(_)
+            | Pending::Revert(_) => "continue? (y/n)".to_string(),
+            Pending::Export => "export as? (Esc to cancel)".to_string(),
+        });
+        lines
+    }
+}
+
+/// Panel editing the ACPI parameters on the kernel command line.
+struct CmdlinePanel<'a> {
+    kernel: String,
+    params: ListState,
+    args: Vec<Vec<String>>,
+    input: Option<TextArea<'a>>,
+}
+
+impl CmdlinePanel<'_> {
+    fn selected(&self) -> &'static cmdline::Param {
+        &cmdline::PARAMS[self.params.selected().unwrap_or(0)]
+    }
+}
+
+/// Panel browsing the applies recorded in the journal.
+struct HistoryPanel {
+    /// Oldest first, as numbered by `acpied history`.
+    entries: Vec<journal::Entry>,
+    /// Listed newest first.
+    list: ListState,
+    /// Entry the selected one is compared with instead of the one before.
+    base: Option<usize>,
+    lines: Vec<String>,
+    scroll: u16,
+}
+
+impl HistoryPanel {
+    fn selected(&self) -> usize {
+        self.entries.len() - 1 - self.list.selected().unwrap_or(0)
+    }
+
+    /// Describe the selected entry and diff it with the base or the one
+    /// before.
+    fn update(&mut self) {
+        let index = self.selected();
+        let old = match self.base {
+            Some(base) if base != index => Some(base),
+            _ => index.checked_sub(1),
+        };
+        self.lines = self.entries[index].describe();
+        self.lines.push(String::new());
+        self.lines.push(match old {
+            Some(old) => format!("compared with #{}", old + 1),
+            None => "compared with the firmware tables".to_string(),
+        });
+        self.lines.extend(journal::diff(
+            old.map(|old| &self.entries[old]),
+            &self.entries[index],
+        ));
+        self.scroll = 0;
+    }
+}
+
+/// Panel browsing the versions of the modified tables.
+struct VersionsPanel<'a> {
+    versions: Vec<Version>,
+    list: ListState,
+    lines: Vec<String>,
+    scroll: u16,
+    /// Name of a checkpoint being typed.
+    input: Option<TextArea<'a>>,
+}
+
+impl VersionsPanel<'_> {
+    fn selected(&self) -> &Version {
+        &self.versions[self.list.selected().unwrap_or(0)]
+    }
+
+    fn update(&mut self) {
+        self.lines =
+            versions::show(&self.selected().id, None).unwrap_or_else(|e| vec![format!("{:#}", e)]);
+        self.scroll = 0;
+    }
+}
+
+/// Panel listing the variants of the modified tables.
+struct VariantsPanel<'a> {
+    variants: Vec<String>,
+    list: ListState,
+    /// Modified tables of the selected variant.
+    tables: Vec<String>,
+    /// Name of a variant being created, and whether it starts from the
+    /// firmware tables.
+    input: Option<(TextArea<'a>, bool)>,
+}
+
+impl VariantsPanel<'_> {
+    fn selected(&self) -> &str {
+        &self.variants[self.list.selected().unwrap_or(0)]
+    }
+
+    fn update(&mut self) {
+        self.tables =
+            versions::variant_tables(self.selected()).unwrap_or_else(|e| vec![format!("{:#}", e)]);
+    }
+}
+
+/// Diff of a table against its firmware original, shown in place of the
+/// content.
+struct DiffPanel {
+    dsl_file: String,
+    side_by_side: bool,
+    diff: Diff,
+    /// Columns the side by side diff was laid out for, 0 for unified.
+    width: usize,
+    scroll: usize,
+    /// Hunk last jumped to.
+    hunk: usize,
+}
+
+impl DiffPanel {
+    /// Lay the diff out again, side by side in `width` columns if set.
+    fn update(&mut self, width: usize) -> Result<()> {
+        self.diff = if self.side_by_side {
+            diff::side_by_side(&self.dsl_file, width)?
+        } else {
+            diff::unified(&self.dsl_file)?
+        };
+        self.width = width;
+        self.scroll = self.scroll.min(self.diff.lines.len().saturating_sub(1));
+        Ok(())
+    }
+
+    fn goto_hunk(&mut self, hunk: usize) {
+        if let Some(line) = self.diff.hunks.get(hunk) {
+            self.hunk = hunk;
+            self.scroll = *line;
+        }
+    }
+
+    /// Jump to the first hunk below the top line, or above it if `back`.
+    fn next_hunk(&mut self, back: bool) {
+        let scroll = self.scroll;
+        let hunks = &self.diff.hunks;
+        let hunk = if back {
+            hunks.iter().rposition(|&line| line < scroll)
+        } else {
+            hunks.iter().position(|&line| line > scroll)
+        };
+        if let Some(hunk) = hunk {
+            self.goto_hunk(hunk);
+        }
+    }
+
+    fn scroll_by(&mut self, lines: isize) {
+        let max = self.diff.lines.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+}
+
+/// Long running job executed on a worker thread.
+struct Task {
+    name: &'static str,
+    cancel: Arc<AtomicBool>,
+    receiver: Receiver<Message>,
 }
 
 struct AcpiEditor<'a> {
     files: StatefulList<String>,
     modified: StatefulList<String>,
+    /// Modified tables left out of applies.
+    excluded: Vec<String>,
+    focus: Focus,
     content: TextArea<'a>,
+    /// First line shown, following the scrolling of the text area.
+    content_top: usize,
+    content_height: usize,
+    /// Firmware source of the table edited, and the changes made to it.
+    origin: Vec<String>,
+    changes: Vec<diff::Change>,
+    /// Edits were made since `changes` was computed, it is refreshed when idle.
+    changes_stale: bool,
     last_char: char,
     mode: Mode,
     search_pattern: TextArea<'a>,
     log: TextArea<'a>,
+    pending: Option<Pending>,
+    cmdline: Option<CmdlinePanel<'a>>,
+    history: Option<HistoryPanel>,
+    versions: Option<VersionsPanel<'a>>,
+    variants: Option<VariantsPanel<'a>>,
+    variant: Option<String>,
+    diff: Option<DiffPanel>,
+    source: Option<PathBuf>,
+    task: Option<Task>,
+    spinner: usize,
 }
 
 impl AcpiEditor<'_> {
-    fn new() -> Self {
-        let script = PathBuf::from("/bin/acpied-init");
-
-        let output = Command::new("bash")
-            .arg(&script)
-            .output()
-            .expect("fail to execute script!");
-        if !output.status.success() {
-            panic!("script executed with error code!");
-        }
-
-        let mut files: Vec<String> = vec![];
-
-        for entry in fs::read_dir(MODIFIEDDIR).unwrap() {
-            let p = entry.unwrap().path();
-            let file_name = String::from(p.file_name().unwrap().to_str().unwrap());
-            files.push(file_name);
-        }
-
-        files.sort();
-
-        // let modified: Vec<String> = vec![];
-
+    fn new(redump: bool) -> Self {
         let mut editor = Self {
-            files: StatefulList::with_items(files),
+            files: StatefulList::with_items(Vec::<String>::new()),
             modified: StatefulList::with_items(Vec::<String>::new()),
+            excluded: vec![],
+            focus: Focus::Content,
             content: TextArea::default(),
+            content_top: 0,
+            content_height: 0,
+            origin: vec![],
+            changes: vec![],
+            changes_stale: false,
             last_char: ' ',
             mode: Mode::Normal,
             search_pattern: TextArea::default(),
             log: TextArea::default(),
+            pending: None,
+            cmdline: None,
+            history: None,
+            versions: None,
+            variants: None,
+            variant: None,
+            diff: None,
+            source: None,
+            task: None,
+            spinner: 0,
         };
 
-        let block = editor
-            .log
-            .block()
-            .cloned()
-            .unwrap_or_else(|| Block::default().borders(Borders::ALL).title(LOGFILE));
+        let block = editor.log.block().cloned().unwrap_or_else(|| {
+            Block::default()
+                .borders(Borders::ALL)
+                .title(config::get().log.display().to_string())
+        });
         editor.log.set_block(block);
         editor.log.set_max_histories(MAX_HISTORY_SIZE);
         editor
             .log
             .set_cursor_style(Style::default().add_modifier(Modifier::HIDDEN));
 
+        editor.open_workspace(redump);
+
         editor
     }
 
+    /// Open the workspace, dumping the tables again if `redump` is set. Once
+    /// opened, an imported workspace is imported again instead; it is only
+    /// left for the running firmware by `acpied --redump`.
+    fn open_workspace(&mut self, redump: bool) {
+        let source = self.source.clone().filter(|_| redump);
+        self.spawn_task("opening workspace", move |progress, _| {
+            Message::Initialized(match source {
+                Some(source) => workspace::import(&source, true, progress),
+                None => workspace::open(redump, progress),
+            })
+        });
+    }
+
+    fn redump(&mut self) {
+        if self.busy() {
+            return;
+        }
+        if self.modified.items.is_empty() {
+            self.open_workspace(true);
+        } else {
+            self.pending = Some(Pending::Redump);
+            self.switch_mode(Mode::Confirm);
+        }
+    }
+
+    fn spawn_task<F>(&mut self, name: &'static str, f: F)
+    where
+        F: FnOnce(&dyn Fn(&str), &AtomicBool) -> Message + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let task_cancel = cancel.clone();
+        thread::spawn(move || {
+            let progress = |line: &str| {
+                let _ = sender.send(Message::Log(line.to_string()));
+            };
+            let message = f(&progress, &task_cancel);
+            let _ = sender.send(message);
+        });
+        self.task = Some(Task {
+            name,
+            cancel,
+            receiver,
+        });
+    }
+
+    fn cancel_task(&mut self) {
+        if let Some(task) = &self.task {
+            task.cancel.store(true, Ordering::SeqCst);
+            let line = format!("cancelling {}", task.name);
+            self.update_log(line.as_str());
+        }
+    }
+
+    fn busy(&mut self) -> bool {
+        if let Some(task) = &self.task {
+            let line = format!("busy {}, try again later", task.name);
+            self.update_log(line.as_str());
+            return true;
+        }
+        false
+    }
+
+    /// Drain messages from the running task, if any.
+    fn poll_task(&mut self) {
+        loop {
+            let message = match &self.task {
+                Some(task) => match task.receiver.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => return,
+                    Err(TryRecvError::Disconnected) => {
+                        let line = format!("{} aborted", task.name);
+                        self.task = None;
+                        self.update_log(line.as_str());
+                        return;
+                    }
+                },
+                None => return,
+            };
+            match message {
+                Message::Log(line) => self.update_log(line.as_str()),
+                Message::Initialized(result) => {
+                    self.task = None;
+                    match result {
+                        Ok(workspace) => {
+                            let line = format!(
+                                "{} tables, {} modified",
+                                workspace.tables.len(),
+                                workspace.modified.len()
+                            );
+                            self.files = StatefulList::with_items(workspace.tables);
+                            self.modified = StatefulList::with_items(workspace.modified);
+                            self.excluded = workspace::excluded();
+                            self.content = TextArea::default();
+                            self.source = workspace::source();
+                            self.variant = versions::variant().ok();
+                            self.update_log(line.as_str());
+                        }
+                        Err(e) => {
+                            self.update_log(format!("fail to open workspace: {:#}", e).as_str())
+                        }
+                    }
+                }
+                Message::Planned { plan, dry_run } => {
+                    self.task = None;
+                    match plan {
+                        Ok(plan) => {
+                            self.pending = Some(if dry_run {
+                                Pending::DryRun(plan)
+                            } else {
+                                Pending::Apply(plan)
+                            });
+                            self.switch_mode(Mode::Confirm);
+                        }
+                        Err(e) => self.update_log(
+                            format!("validation failed, nothing applied: {:#}", e).as_str(),
+                        ),
+                    }
+                }
+                Message::Exported(format, result) => {
+                    self.task = None;
+                    match result {
+                        Ok(path) => self.update_log(
+                            format!("{} written to {}", format.describe(), path.display()).as_str(),
+                        ),
+                        Err(e) => self.update_log(format!("export failed: {:#}", e).as_str()),
+                    }
+                }
+                Message::Loaded(dsl_file, result) => {
+                    self.task = None;
+                    match result {
+                        Ok(dir) => self.update_log(
+                            format!("{} loaded as {}", dsl_file, dir.display()).as_str(),
+                        ),
+                        Err(e) => self.update_log(format!("load failed: {:#}", e).as_str()),
+                    }
+                }
+                Message::MethodReplaced(result) => {
+                    self.task = None;
+                    match result {
+                        Ok((method, size)) => self.update_log(
+                            format!("{} replaced through custom_method ({} bytes)", method, size)
+                                .as_str(),
+                        ),
+                        Err(e) => {
+                            self.update_log(format!("method replace failed: {:#}", e).as_str())
+                        }
+                    }
+                }
+                Message::CmdlineUpdated(key, result) => {
+                    self.task = None;
+                    match result {
+                        Ok((commands, args)) => {
+                            if let Some(panel) = self.cmdline.as_mut() {
+                                panel.args = args;
+                            }
+                            for command in commands {
+                                self.update_log(command.as_str());
+                            }
+                        }
+                        Err(e) => {
+                            self.update_log(format!("fail to update {}: {:#}", key, e).as_str())
+                        }
+                    }
+                }
+                Message::EfiVarPlanned(plan) => {
+                    self.task = None;
+                    match plan {
+                        Ok(plan) => {
+                            self.pending = Some(Pending::ApplyEfiVar(plan));
+                            self.switch_mode(Mode::Confirm);
+                        }
+                        Err(e) => self.update_log(
+                            format!("validation failed, nothing applied: {:#}", e).as_str(),
+                        ),
+                    }
+                }
+                Message::EfiVarInstalled(result) => {
+                    self.task = None;
+                    match result {
+                        Ok(boot_entry) => self.update_log(
+                            format!("tables written to efi variables for {}", boot_entry).as_str(),
+                        ),
+                        Err(e) => self.update_log(format!("apply failed: {:#}", e).as_str()),
+                    }
+                }
+                Message::Installed(result) => {
+                    self.task = None;
+                    match result {
+                        Ok(installed) => {
+                            self.update_log(
+                                format!("original initrd: {}", installed.previous_initrd.display())
+                                    .as_str(),
+                            );
+                            self.update_log(
+                                format!(
+                                    "successfully set initrd for {}: {}",
+                                    installed.boot_entry,
+                                    installed.initrd.display()
+                                )
+                                .as_str(),
+                            );
+                        }
+                        Err(e) => self.update_log(format!("apply failed: {:#}", e).as_str()),
+                    }
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        self.spinner = (self.spinner + 1) % SPINNER.len();
+        self.refresh_changes();
+    }
+
+    /// Compute the changes from the firmware source again after edits.
+    fn refresh_changes(&mut self) {
+        if self.changes_stale {
+            self.changes = diff::changes(&self.origin, self.content.lines());
+            self.changes_stale = false;
+        }
+    }
+
+    /// Move the cursor to the start of `line`, if the text area can reach it.
+    fn jump_to(&mut self, line: usize) {
+        match u16::try_from(line) {
+            Ok(row) => self.content.move_cursor(CursorMove::Jump(row, 0)),
+            Err(_) => self.update_log(format!("cannot move to line {}", line + 1).as_str()),
+        }
+    }
+
     fn select_dsl_file(&mut self) {
         let dsl_file = self.files.items[self.files.state.selected().unwrap_or(0)].to_owned();
-        let dsl_file_path = PathBuf::from(MODIFIEDDIR).join(&dsl_file);
+        let dsl_file_path = workspace::modified_dir().join(&dsl_file);
         let text = fs::read_to_string(&dsl_file_path).unwrap();
-        self.content = TextArea::from(text.lines());
+        self.set_content(&dsl_file, text.lines().map(String::from).collect());
+        self.update_log(format!("{} selected", &dsl_file).as_str());
+    }
+
+    /// Edit `lines` as the source of `dsl_file`, marking the changes from its
+    /// firmware original.
+    fn set_content(&mut self, dsl_file: &str, lines: Vec<String>) {
+        self.content = TextArea::new(lines.clone());
         let block = self.content.block().cloned().unwrap_or_else(|| {
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!("{}", &dsl_file))
+                .title(dsl_file.to_owned())
         });
         self.content.set_block(block);
         self.content.set_max_histories(MAX_HISTORY_SIZE);
         self.content
             .set_line_number_style(Style::default().bg(Color::Reset).fg(Color::White));
-        self.update_log(format!("{} selected", &dsl_file).as_str());
+        self.content_top = 0;
+        self.origin = fs::read_to_string(workspace::origin_dir().join(dsl_file))
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+        self.changes = diff::changes(&self.origin, &lines);
+        self.changes_stale = false;
     }
 
     fn next_dsl_file(&mut self) {
+        if self.files.items.is_empty() {
+            return;
+        }
         self.files.next();
         self.select_dsl_file();
     }
 
     fn previous_dsl_file(&mut self) {
+        if self.files.items.is_empty() {
+            return;
+        }
         self.files.previous();
         self.select_dsl_file();
     }
 
+    /// Move the focus to the next panel, skipping the modified list when empty.
+    fn cycle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Content => Focus::Files,
+            Focus::Files if self.modified.items.is_empty() => Focus::Content,
+            Focus::Files => Focus::Modified,
+            Focus::Modified => Focus::Content,
+        };
+        if self.focus == Focus::Modified && self.modified.state.selected().is_none() {
+            self.modified.state.select(Some(0));
+        }
+    }
+
+    /// Keys for the focused file list.
+    fn list_input(&mut self, input: Input) {
+        match (self.focus, input.key) {
+            (_, Key::Tab) => self.cycle_focus(),
+            (_, Key::Esc) if self.busy() => self.cancel_task(),
+            (_, Key::Esc) => self.focus = Focus::Content,
+            (Focus::Files, Key::Down | Key::Char('j')) => self.next_dsl_file(),
+            (Focus::Files, Key::Up | Key::Char('k')) => self.previous_dsl_file(),
+            (Focus::Files, Key::Enter) => self.focus = Focus::Content,
+            (Focus::Modified, _) if self.modified.items.is_empty() => {}
+            (Focus::Modified, Key::Down | Key::Char('j')) => self.modified.next(),
+            (Focus::Modified, Key::Up | Key::Char('k')) => self.modified.previous(),
+            (Focus::Modified, Key::Enter | Key::Char('o' | 'd' | 'r')) => {
+                if !self.open_modified() {
+                    return;
+                }
+                match input.key {
+                    Key::Char('d') => self.open_diff(),
+                    Key::Char('r') => self.confirm_revert(),
+                    _ => self.focus = Focus::Content,
+                }
+            }
+            (Focus::Modified, Key::Char('x')) => self.toggle_excluded(),
+            _ => {}
+        }
+    }
+
+    fn selected_modified(&self) -> Option<String> {
+        self.modified
+            .state
+            .selected()
+            .and_then(|index| self.modified.items.get(index))
+            .cloned()
+    }
+
+    /// Edit the table selected in the modified list. Returns whether it was
+    /// found in the workspace.
+    fn open_modified(&mut self) -> bool {
+        let dsl_file = match self.selected_modified() {
+            Some(dsl_file) => dsl_file,
+            None => return false,
+        };
+        match self.files.items.iter().position(|f| *f == dsl_file) {
+            Some(index) => {
+                if self.files.state.selected() != Some(index) {
+                    self.files.state.select(Some(index));
+                    self.select_dsl_file();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Leave the table selected in the modified list out of applies, or take
+    /// it back in.
+    fn toggle_excluded(&mut self) {
+        let dsl_file = match self.selected_modified() {
+            Some(dsl_file) => dsl_file,
+            None => return,
+        };
+        let exclude = !self.excluded.contains(&dsl_file);
+        match workspace::set_excluded(&dsl_file, exclude) {
+            Ok(()) => {
+                self.excluded = workspace::excluded();
+                let line = if exclude {
+                    format!("{} excluded from apply", dsl_file)
+                } else {
+                    format!("{} included in apply", dsl_file)
+                };
+                self.update_log(&line);
+            }
+            Err(e) => self.update_log(format!("fail to exclude {}: {:#}", dsl_file, e).as_str()),
+        }
+    }
+
     fn update_log(&mut self, line: &str) {
         let now = Utc::now();
         let new_line = format!(
@@ -176,9 +811,8 @@ impl AcpiEditor<'_> {
         if let Ok(mut log_file) = OpenOptions::new()
             .read(true)
             .create(true)
-            .write(true)
             .append(true)
-            .open(LOGFILE)
+            .open(&config::get().log)
         {
             writeln!(log_file, "{}", new_line).unwrap();
         }
@@ -191,6 +825,8 @@ impl AcpiEditor<'_> {
                 let style = Style::default().bg(Color::White).fg(Color::Black);
                 self.content.set_cursor_style(style);
             }
+            // the tables do not change under a running task
+            Mode::Insert if self.busy() => {}
             Mode::Insert => {
                 self.mode = Mode::Insert;
                 let style = Style::default()
@@ -212,6 +848,24 @@ impl AcpiEditor<'_> {
                         .add_modifier(Modifier::BOLD),
                 );
             }
+            Mode::Confirm => {
+                self.mode = Mode::Confirm;
+            }
+            Mode::Cmdline => {
+                self.mode = Mode::Cmdline;
+            }
+            Mode::History => {
+                self.mode = Mode::History;
+            }
+            Mode::Versions => {
+                self.mode = Mode::Versions;
+            }
+            Mode::Variants => {
+                self.mode = Mode::Variants;
+            }
+            Mode::Diff => {
+                self.mode = Mode::Diff;
+            }
         }
     }
 
@@ -252,61 +906,102 @@ impl AcpiEditor<'_> {
         }
     }
 
-    fn to_bottom(&mut self) {
+    fn goto_bottom(&mut self) {
         self.content.move_cursor(CursorMove::Bottom);
         self.content.move_cursor(CursorMove::Head);
     }
 
-    fn to_line_head(&mut self) {
+    fn goto_line_head(&mut self) {
         self.content.move_cursor(CursorMove::Head);
     }
 
-    fn to_line_end(&mut self) {
+    fn goto_line_end(&mut self) {
         self.content.move_cursor(CursorMove::End);
     }
 
-    fn to_next_page(&mut self) {
+    fn goto_next_page(&mut self) {
+        self.content_top += self.content_height;
         self.content.scroll(Scrolling::PageDown);
     }
 
-    fn to_previous_page(&mut self) {
+    fn goto_previous_page(&mut self) {
+        self.content_top = self.content_top.saturating_sub(self.content_height);
         self.content.scroll(Scrolling::PageUp);
     }
 
+    /// Move the cursor to the next change from the firmware source, or the
+    /// previous one if `back`.
+    fn goto_change(&mut self, back: bool) {
+        self.refresh_changes();
+        let (row, _) = self.content.cursor();
+        let len = self.content.lines().len();
+        let mut lines = self.changes.iter().map(|c| c.line(len));
+        let line = if back {
+            lines.rfind(|&line| line < row)
+        } else {
+            lines.find(|&line| line > row)
+        };
+        match line {
+            Some(line) => self.jump_to(line),
+            None => self.update_log("no more changes"),
+        }
+    }
+
+    /// Bring the change under the cursor back to the firmware source.
+    fn revert_change(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].to_owned(),
+            None => return,
+        };
+        self.refresh_changes();
+        let (row, _) = self.content.cursor();
+        let mut lines = self.content.clone().into_lines();
+        let change = match self.changes.iter().find(|c| c.contains(row, lines.len())) {
+            Some(change) => change.clone(),
+            None => return,
+        };
+        lines.splice(
+            change.new.clone(),
+            self.origin[change.old.clone()].iter().cloned(),
+        );
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let line = change.new.start.min(lines.len() - 1);
+        self.set_content(&dsl_file, lines);
+        self.jump_to(line);
+        self.switch_mode(Mode::Normal);
+        self.write();
+        self.update_log(format!("change at line {} reverted", line + 1).as_str());
+    }
+
     fn write(&mut self) {
-        let dsl_file = self.files.items[self.files.state.selected().unwrap_or(0)].to_owned();
-        let modified_dsl_file = PathBuf::from(MODIFIEDDIR).join(&dsl_file);
-        let origin_dsl_file = PathBuf::from(ORIGINDIR).join(&dsl_file);
-        let mut text = self.content.clone().into_lines().join("\n");
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].to_owned(),
+            None => return,
+        };
+        let modified_dsl_file = workspace::modified_dir().join(&dsl_file);
+        let lines = self.content.clone().into_lines();
+        self.changes_stale = true;
+        let mut text = lines.join("\n");
         text.push('\n');
         fs::write(&modified_dsl_file, text).expect("fail to write conent to dsl file!");
 
-        let mut modified_dsl_file = fs::File::open(modified_dsl_file).unwrap();
-        let mut origin_dsl_file = fs::File::open(origin_dsl_file).unwrap();
-        let mut exist = false;
-
-        for dslfile in self.modified.items.iter() {
-            if dslfile == &dsl_file {
-                exist = true;
-                break;
-            }
-        }
-
-        if !file_diff::diff_files(&mut modified_dsl_file, &mut origin_dsl_file) && !exist {
-            self.modified.items.push(dsl_file);
-            self.modified.items.sort();
-        } else if file_diff::diff_files(&mut modified_dsl_file, &mut origin_dsl_file) && exist {
-            match self.modified.items.binary_search(&dsl_file) {
-                Ok(index) => {
-                    self.modified.items.remove(index);
-                }
-                Err(_) => {}
+        let modified = workspace::is_modified(&dsl_file).unwrap_or(true);
+        match self.modified.items.binary_search(&dsl_file) {
+            Err(index) if modified => self.modified.items.insert(index, dsl_file),
+            Ok(index) if !modified => {
+                self.modified.items.remove(index);
             }
+            _ => {}
         }
     }
 
     fn insert(&mut self, key: KeyEvent) {
-        if self.files.state.selected().is_none() {
+        if self.files.state.selected().is_none() || self.busy() {
             return;
         }
         self.content.input(key);
@@ -336,7 +1031,9 @@ impl AcpiEditor<'_> {
     }
 
     fn try_delete_line(&mut self) {
-        if self.last_char == 'd' {
+        if self.last_char == 'd' && self.busy() {
+            self.last_char = ' ';
+        } else if self.last_char == 'd' {
             self.content.delete_line_by_end();
             self.content.delete_line_by_head();
             self.write();
@@ -348,8 +1045,10 @@ impl AcpiEditor<'_> {
 
     fn try_delete_word(&mut self) {
         if self.last_char == 'd' {
-            self.content.delete_next_word();
-            self.write();
+            if !self.busy() {
+                self.content.delete_next_word();
+                self.write();
+            }
         } else {
             self.next_word();
         }
@@ -357,11 +1056,17 @@ impl AcpiEditor<'_> {
     }
 
     fn delete_char(&mut self) {
+        if self.busy() {
+            return;
+        }
         self.content.delete_next_char();
         self.write();
     }
 
     fn insert_new_line_below(&mut self) {
+        if self.busy() {
+            return;
+        }
         self.content.move_cursor(CursorMove::Head);
         self.content.move_cursor(CursorMove::Down);
         self.content.insert_newline();
@@ -371,6 +1076,9 @@ impl AcpiEditor<'_> {
     }
 
     fn insert_new_line_up(&mut self) {
+        if self.busy() {
+            return;
+        }
         self.content.move_cursor(CursorMove::Head);
         self.content.insert_newline();
         self.write();
@@ -379,70 +1087,732 @@ impl AcpiEditor<'_> {
     }
 
     fn undo(&mut self) {
+        if self.busy() {
+            return;
+        }
         self.content.undo();
         self.write();
     }
 
-    fn apply(&mut self) {
-        if self.modified.items.len() == 0 {
+    /// Validate the modified tables and work out the apply plan. With
+    /// `dry_run` the plan is only shown.
+    fn apply(&mut self, dry_run: bool) {
+        if self.modified.items.is_empty() || self.busy() {
+            return;
+        }
+        if self.source.is_some() {
+            self.update_log("tables imported from a dump can only be exported (Ctrl+E)");
             return;
         }
 
-        let dsl_files = self.modified.items.join(",");
-        match Command::new("acpied-apply").arg(dsl_files).output() {
-            Ok(o) => {
-                if o.status.success() {
-                    let stdout = String::from_utf8(o.stdout).unwrap();
-                    for line in stdout.split("\n") {
-                        if line.len() != 0 {
-                            self.update_log(line);
-                        }
-                    }
-                } else {
-                    let stderr = String::from_utf8(o.stderr).unwrap();
-                    for line in stderr.split("\n") {
-                        self.update_log(line);
-                    }
-                }
-            }
-            Err(e) => {
-                self.update_log(e.to_string().as_str());
-            }
+        let dsl_files = workspace::to_apply(&self.modified.items);
+        if dsl_files.is_empty() {
+            self.update_log("all modified tables are excluded from apply");
+            return;
         }
+        self.spawn_task("validating", move |progress, cancel| Message::Planned {
+            plan: apply::validate_workspace(&dsl_files, progress, cancel)
+                .and_then(|validation| apply::plan(validation, &bootloader::boot_dir())),
+            dry_run,
+        });
     }
-}
 
-fn init_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>> {
-    crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
-    enable_raw_mode()?;
-    let backend = CrosstermBackend::new(io::stdout());
-    let mut terminal = Terminal::new(backend)?;
-    terminal.hide_cursor()?;
-    Ok(terminal)
-}
+    /// Validate the modified SSDTs and work out how to load them from EFI
+    /// variables instead of the initrd.
+    fn apply_efivar(&mut self) {
+        if self.modified.items.is_empty() || self.busy() {
+            return;
+        }
+        if self.source.is_some() {
+            self.update_log("tables imported from a dump can only be exported (Ctrl+E)");
+            return;
+        }
 
-fn reset_terminal() -> Result<()> {
-    disable_raw_mode()?;
-    crossterm::execute!(io::stdout(), LeaveAlternateScreen)?;
-    Ok(())
-}
+        let dsl_files = workspace::to_apply(&self.modified.items);
+        if dsl_files.is_empty() {
+            self.update_log("all modified tables are excluded from apply");
+            return;
+        }
+        self.spawn_task("validating", move |progress, cancel| {
+            Message::EfiVarPlanned(
+                apply::validate_workspace(&dsl_files, progress, cancel).and_then(efivar::plan),
+            )
+        });
+    }
 
-fn draw_file_list<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
-    let items: Vec<ListItem> = editor
-        .files
-        .items
-        .iter()
-        .map(|i| {
-            let lines = vec![Spans::from(i.to_owned())];
-            ListItem::new(lines).style(Style::default())
-        })
-        .collect();
-    let items = List::new(items)
-        .block(
-            Block::default()
+    /// Load the selected SSDT into the running kernel.
+    fn load(&mut self) {
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].to_owned(),
+            None => return,
+        };
+        if self.busy() {
+            return;
+        }
+        self.spawn_task("loading", move |progress, cancel| {
+            let result = runtime::load(&dsl_file, progress, cancel);
+            Message::Loaded(dsl_file, result)
+        });
+    }
+
+    /// Unload the selected SSDT from the running kernel.
+    fn unload(&mut self) {
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].to_owned(),
+            None => return,
+        };
+        match runtime::unload(&dsl_file) {
+            Ok(()) => self.update_log(format!("{} unloaded", dsl_file).as_str()),
+            Err(e) => self.update_log(format!("unload failed: {:#}", e).as_str()),
+        }
+    }
+
+    /// Replace the method under the cursor in the running kernel.
+    fn replace_method(&mut self) {
+        if self.files.state.selected().is_none() || self.busy() {
+            return;
+        }
+        let lines = self.content.lines().to_vec();
+        let (row, _) = self.content.cursor();
+        self.spawn_task("replacing method", move |_, _| {
+            Message::MethodReplaced(runtime::custom_method(&lines, row))
+        });
+    }
+
+    /// Show the ACPI parameters of the default boot entry.
+    fn open_cmdline(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let panel = bootloader::default_kernel().and_then(|kernel| {
+            let args = cmdline::current(&kernel)?;
+            Ok(CmdlinePanel {
+                kernel,
+                params: ListState::default(),
+                args,
+                input: None,
+            })
+        });
+        match panel {
+            Ok(mut panel) => {
+                panel.params.select(Some(0));
+                self.cmdline = Some(panel);
+                self.switch_mode(Mode::Cmdline);
+            }
+            Err(e) => self.update_log(format!("fail to read kernel parameters: {:#}", e).as_str()),
+        }
+    }
+
+    fn close_cmdline(&mut self) {
+        self.cmdline = None;
+        self.switch_mode(Mode::Normal);
+    }
+
+    /// Replace the selected parameter by the arguments typed in the panel,
+    /// or remove it if none were typed. A value typed with its key, such as
+    /// `acpi_osi=`, is kept as is.
+    fn save_cmdline(&mut self) {
+        if self.cmdline.is_none() || self.busy() {
+            return;
+        }
+        let panel = match self.cmdline.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        let text = match panel.input.take() {
+            Some(input) => input.into_lines().join(" "),
+            None => return,
+        };
+        let key = panel.selected().key;
+        let set: Vec<String> = bootloader::split_args(&text)
+            .into_iter()
+            .map(|a| {
+                if bootloader::arg_key(&a) == key {
+                    a
+                } else {
+                    format!("{}={}", key, a)
+                }
+            })
+            .collect();
+        let unset = if set.is_empty() {
+            vec![key.to_string()]
+        } else {
+            vec![]
+        };
+        let kernel = panel.kernel.clone();
+        self.spawn_task("updating kernel parameters", move |_, _| {
+            let result = cmdline::update(&kernel, &set, &unset)
+                .and_then(|commands| Ok((commands, cmdline::current(&kernel)?)));
+            Message::CmdlineUpdated(key, result)
+        });
+    }
+
+    fn cmdline_input(&mut self, key: KeyEvent) {
+        let panel = match self.cmdline.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        if let Some(input) = panel.input.as_mut() {
+            match key.code {
+                KeyCode::Esc => panel.input = None,
+                KeyCode::Enter => self.save_cmdline(),
+                _ => {
+                    input.input(key);
+                }
+            }
+            return;
+        }
+
+        let count = cmdline::PARAMS.len();
+        let index = panel.params.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.close_cmdline(),
+            KeyCode::Up | KeyCode::Char('k') => {
+                panel.params.select(Some((index + count - 1) % count))
+            }
+            KeyCode::Down | KeyCode::Char('j') => panel.params.select(Some((index + 1) % count)),
+            KeyCode::Enter | KeyCode::Char('e') => {
+                // an empty value keeps its key, telling `acpi_osi=` from no
+                // argument at all
+                let values: Vec<&str> = panel.args[index]
+                    .iter()
+                    .map(|a| match a.split_once('=') {
+                        Some((_, value)) if !value.is_empty() => value,
+                        _ => a.as_str(),
+                    })
+                    .collect();
+                let mut input = TextArea::default();
+                input.insert_str(values.join(" ").as_str());
+                panel.input = Some(input);
+            }
+            KeyCode::Char('x') => {
+                panel.input = Some(TextArea::default());
+                self.save_cmdline();
+            }
+            _ => {}
+        }
+    }
+
+    /// Browse the applies recorded in the journal.
+    fn open_history(&mut self) {
+        let entries = match journal::entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.update_log(format!("fail to read the journal: {:#}", e).as_str());
+                return;
+            }
+        };
+        if entries.is_empty() {
+            self.update_log("no applies recorded");
+            return;
+        }
+        let mut panel = HistoryPanel {
+            entries,
+            list: ListState::default(),
+            base: None,
+            lines: vec![],
+            scroll: 0,
+        };
+        panel.list.select(Some(0));
+        panel.update();
+        self.history = Some(panel);
+        self.switch_mode(Mode::History);
+    }
+
+    fn history_input(&mut self, key: KeyEvent) {
+        let panel = match self.history.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        let count = panel.entries.len();
+        let index = panel.list.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.history = None;
+                self.switch_mode(Mode::Normal);
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                panel.list.select(Some((index + count - 1) % count));
+                panel.update();
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                panel.list.select(Some((index + 1) % count));
+                panel.update();
+            }
+            KeyCode::Char('m') => {
+                let selected = panel.selected();
+                panel.base = (panel.base != Some(selected)).then_some(selected);
+                panel.update();
+            }
+            KeyCode::PageDown | KeyCode::Char('J') => {
+                let max = panel.lines.len().saturating_sub(1) as u16;
+                panel.scroll = (panel.scroll + 10).min(max);
+            }
+            KeyCode::PageUp | KeyCode::Char('K') => {
+                panel.scroll = panel.scroll.saturating_sub(10);
+            }
+            _ => {}
+        }
+    }
+
+    /// Browse the versions of the modified tables.
+    fn open_versions(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let versions = match versions::log(None) {
+            Ok(versions) if !versions.is_empty() => versions,
+            Ok(_) => return,
+            Err(e) => {
+                self.update_log(format!("fail to read the versions: {:#}", e).as_str());
+                return;
+            }
+        };
+        let mut panel = VersionsPanel {
+            versions,
+            list: ListState::default(),
+            lines: vec![],
+            scroll: 0,
+            input: None,
+        };
+        panel.list.select(Some(0));
+        panel.update();
+        self.versions = Some(panel);
+        self.switch_mode(Mode::Versions);
+    }
+
+    /// Record the tables as a version named as typed in the panel.
+    fn checkpoint(&mut self) {
+        let message = match self.versions.as_mut().and_then(|p| p.input.take()) {
+            Some(input) => input.into_lines().join(" "),
+            None => return,
+        };
+        let message = match message.trim() {
+            "" => "checkpoint",
+            message => message,
+        };
+        match versions::checkpoint(message) {
+            Ok(Some(id)) => {
+                self.update_log(format!("version {} recorded: {}", id, message).as_str());
+                self.open_versions();
+            }
+            Ok(None) => self.update_log("no changes since the last version"),
+            Err(e) => self.update_log(format!("fail to record a version: {:#}", e).as_str()),
+        }
+    }
+
+    /// Bring the modified tables back to version `id` and reload them.
+    fn restore(&mut self, id: &str) {
+        if self.busy() {
+            return;
+        }
+        match versions::restore(id) {
+            Ok(_) => self.update_log(format!("tables back to version {}", id).as_str()),
+            Err(e) => {
+                self.update_log(format!("fail to return to {}: {:#}", id, e).as_str());
+                return;
+            }
+        }
+        self.reload_tables();
+    }
+
+    /// Read the tables again after the versions changed them, keeping the
+    /// selected one.
+    fn reload_tables(&mut self) {
+        self.variant = versions::variant().ok();
+        let selected = self
+            .files
+            .state
+            .selected()
+            .map(|i| self.files.items[i].clone());
+        match workspace::tables().and_then(|tables| Ok((tables, workspace::modified()?))) {
+            Ok((tables, modified)) => {
+                self.files = StatefulList::with_items(tables);
+                self.modified = StatefulList::with_items(modified);
+                self.excluded = workspace::excluded();
+                self.content = TextArea::default();
+                if let Some(index) =
+                    selected.and_then(|s| self.files.items.iter().position(|f| *f == s))
+                {
+                    self.files.state.select(Some(index));
+                    self.select_dsl_file();
+                }
+            }
+            Err(e) => self.update_log(format!("fail to reload the tables: {:#}", e).as_str()),
+        }
+    }
+
+    fn versions_input(&mut self, key: KeyEvent) {
+        let panel = match self.versions.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        if let Some(input) = panel.input.as_mut() {
+            match key.code {
+                KeyCode::Esc => panel.input = None,
+                KeyCode::Enter => self.checkpoint(),
+                _ => {
+                    input.input(key);
+                }
+            }
+            return;
+        }
+
+        let count = panel.versions.len();
+        let index = panel.list.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.versions = None;
+                self.switch_mode(Mode::Normal);
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                panel.list.select(Some((index + count - 1) % count));
+                panel.update();
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                panel.list.select(Some((index + 1) % count));
+                panel.update();
+            }
+            KeyCode::PageDown | KeyCode::Char('J') => {
+                let max = panel.lines.len().saturating_sub(1) as u16;
+                panel.scroll = (panel.scroll + 10).min(max);
+            }
+            KeyCode::PageUp | KeyCode::Char('K') => {
+                panel.scroll = panel.scroll.saturating_sub(10);
+            }
+            KeyCode::Char('c') => panel.input = Some(TextArea::default()),
+            KeyCode::Enter | KeyCode::Char('r') => {
+                self.pending = Some(Pending::Restore(panel.selected().id.clone()));
+                self.versions = None;
+                self.switch_mode(Mode::Confirm);
+            }
+            _ => {}
+        }
+    }
+
+    /// List the variants of the modified tables.
+    fn open_variants(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let variants = match versions::variants() {
+            Ok(variants) if !variants.is_empty() => variants,
+            Ok(_) => return,
+            Err(e) => {
+                self.update_log(format!("fail to read the variants: {:#}", e).as_str());
+                return;
+            }
+        };
+        let current = self.variant.clone().unwrap_or_default();
+        let mut panel = VariantsPanel {
+            list: ListState::default(),
+            tables: vec![],
+            input: None,
+            variants,
+        };
+        let index = panel.variants.iter().position(|v| *v == current);
+        panel.list.select(Some(index.unwrap_or(0)));
+        panel.update();
+        self.variants = Some(panel);
+        self.switch_mode(Mode::Variants);
+    }
+
+    fn switch_variant(&mut self, name: &str) -> bool {
+        if self.busy() {
+            return false;
+        }
+        match versions::switch_variant(name) {
+            Ok(()) => {
+                self.update_log(format!("switched to variant {}", name).as_str());
+                self.reload_tables();
+                true
+            }
+            Err(e) => {
+                self.update_log(format!("fail to switch to {}: {:#}", name, e).as_str());
+                false
+            }
+        }
+    }
+
+    /// Create the variant named in the panel.
+    fn new_variant(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let (name, from_firmware) = match self.variants.as_mut().and_then(|p| p.input.take()) {
+            Some((input, from_firmware)) => (input.into_lines().join(""), from_firmware),
+            None => return,
+        };
+        let name = name.trim();
+        match versions::new_variant(name, from_firmware) {
+            Ok(()) => {
+                self.update_log(format!("switched to new variant {}", name).as_str());
+                self.reload_tables();
+                self.open_variants();
+            }
+            Err(e) => self.update_log(format!("fail to create {}: {:#}", name, e).as_str()),
+        }
+    }
+
+    fn variants_input(&mut self, key: KeyEvent) {
+        let panel = match self.variants.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        if let Some((input, _)) = panel.input.as_mut() {
+            match key.code {
+                KeyCode::Esc => panel.input = None,
+                KeyCode::Enter => self.new_variant(),
+                _ => {
+                    input.input(key);
+                }
+            }
+            return;
+        }
+
+        let count = panel.variants.len();
+        let index = panel.list.selected().unwrap_or(0);
+        let name = panel.selected().to_string();
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.variants = None;
+                self.switch_mode(Mode::Normal);
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                panel.list.select(Some((index + count - 1) % count));
+                panel.update();
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                panel.list.select(Some((index + 1) % count));
+                panel.update();
+            }
+            KeyCode::Char('n') => panel.input = Some((TextArea::default(), false)),
+            KeyCode::Char('f') => panel.input = Some((TextArea::default(), true)),
+            KeyCode::Enter if self.switch_variant(&name) => self.open_variants(),
+            KeyCode::Char('a') if self.switch_variant(&name) => {
+                self.variants = None;
+                self.switch_mode(Mode::Normal);
+                self.apply(false);
+            }
+            KeyCode::Char('d') => {
+                self.pending = Some(Pending::DeleteVariant(name));
+                self.variants = None;
+                self.switch_mode(Mode::Confirm);
+            }
+            _ => {}
+        }
+    }
+
+    /// Show the changes to the selected table.
+    fn open_diff(&mut self) {
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].clone(),
+            None => return,
+        };
+        let mut panel = DiffPanel {
+            dsl_file,
+            side_by_side: false,
+            diff: Diff {
+                lines: vec![],
+                hunks: vec![],
+            },
+            width: 0,
+            scroll: 0,
+            hunk: 0,
+        };
+        match panel.update(0) {
+            Ok(()) if panel.diff.hunks.is_empty() => {
+                self.update_log(format!("{} not modified", panel.dsl_file).as_str())
+            }
+            Ok(()) => {
+                panel.goto_hunk(0);
+                self.diff = Some(panel);
+                self.switch_mode(Mode::Diff);
+            }
+            Err(e) => self.update_log(format!("fail to diff {}: {:#}", panel.dsl_file, e).as_str()),
+        }
+    }
+
+    fn diff_input(&mut self, key: KeyEvent) {
+        let panel = match self.diff.as_mut() {
+            Some(panel) => panel,
+            None => return,
+        };
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.diff = None;
+                self.switch_mode(Mode::Normal);
+            }
+            KeyCode::Char('n') | KeyCode::Char(']') => panel.next_hunk(false),
+            KeyCode::Char('N') | KeyCode::Char('[') => panel.next_hunk(true),
+            KeyCode::Down | KeyCode::Char('j') => panel.scroll_by(1),
+            KeyCode::Up | KeyCode::Char('k') => panel.scroll_by(-1),
+            KeyCode::PageDown | KeyCode::Char('J') => panel.scroll_by(20),
+            KeyCode::PageUp | KeyCode::Char('K') => panel.scroll_by(-20),
+            KeyCode::Char('g') => panel.scroll = 0,
+            KeyCode::Char('G') => panel.scroll_by(isize::MAX),
+            KeyCode::Char('s') => {
+                panel.side_by_side = !panel.side_by_side;
+                // laid out again for the panel width when drawn
+                panel.width = usize::MAX;
+            }
+            _ => {}
+        }
+    }
+
+    /// Ask whether to undo all edits to the selected table.
+    fn confirm_revert(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let dsl_file = match self.files.state.selected() {
+            Some(index) => self.files.items[index].clone(),
+            None => return,
+        };
+        if self.modified.items.binary_search(&dsl_file).is_err() {
+            self.update_log(format!("{} not modified", dsl_file).as_str());
+            return;
+        }
+        self.pending = Some(Pending::Revert(dsl_file));
+        self.switch_mode(Mode::Confirm);
+    }
+
+    /// Bring back the firmware original of `dsl_file`.
+    fn revert(&mut self, dsl_file: &str) {
+        let kept = match workspace::revert(dsl_file) {
+            Ok(kept) => kept,
+            Err(e) => {
+                self.update_log(format!("fail to revert {}: {:#}", dsl_file, e).as_str());
+                return;
+            }
+        };
+        if let Ok(index) = self.modified.items.binary_search(&dsl_file.to_string()) {
+            self.modified.items.remove(index);
+        }
+        let selected = self
+            .files
+            .state
+            .selected()
+            .is_some_and(|index| self.files.items[index] == dsl_file);
+        if kept {
+            if selected {
+                self.set_content(dsl_file, self.origin.clone());
+            }
+            self.update_log(format!("{} reverted to the firmware original", dsl_file).as_str());
+        } else {
+            self.reload_tables();
+            self.update_log(format!("{} removed", dsl_file).as_str());
+        }
+    }
+
+    /// Ask for the format to export the modified tables in.
+    fn export(&mut self) {
+        if self.modified.items.is_empty() || self.busy() {
+            return;
+        }
+        self.pending = Some(Pending::Export);
+        self.switch_mode(Mode::Confirm);
+    }
+
+    /// Write the modified tables in `format` to the workspace.
+    fn export_as(&mut self, format: Format) {
+        self.switch_mode(Mode::Normal);
+        self.pending = None;
+
+        let dsl_files = self.modified.items.clone();
+        self.spawn_task("exporting", move |progress, cancel| {
+            let output = workspace::root().join(format.default_output());
+            Message::Exported(
+                format,
+                export::export(&dsl_files, format, &output, progress, cancel),
+            )
+        });
+    }
+
+    /// Handle a key pressed while a dialog is shown.
+    fn answer(&mut self, c: char) {
+        match (&self.pending, c) {
+            (Some(Pending::Export), c) => {
+                if let Some(format) = Format::ALL.into_iter().find(|f| f.key() == c) {
+                    self.export_as(format);
+                }
+            }
+            (_, 'y' | 'Y') => self.confirm(),
+            (_, 'n' | 'N') => self.cancel(),
+            _ => {}
+        }
+    }
+
+    fn cancel(&mut self) {
+        self.switch_mode(Mode::Normal);
+        match self.pending.take() {
+            Some(Pending::Apply(_)) | Some(Pending::ApplyEfiVar(_)) => {
+                self.update_log("apply cancelled")
+            }
+            Some(Pending::Redump) => self.update_log("re-dump cancelled"),
+            _ => {}
+        }
+    }
+
+    fn confirm(&mut self) {
+        self.switch_mode(Mode::Normal);
+        match self.pending.take() {
+            Some(Pending::Apply(plan)) => {
+                self.spawn_task("applying", move |progress, cancel| {
+                    Message::Installed(apply::install(&plan, progress, cancel))
+                });
+            }
+            Some(Pending::ApplyEfiVar(plan)) => {
+                self.spawn_task("applying", move |progress, cancel| {
+                    let result =
+                        efivar::install(&plan, progress, cancel).map(|_| plan.boot_entry.clone());
+                    Message::EfiVarInstalled(result)
+                });
+            }
+            Some(Pending::Redump) => self.open_workspace(true),
+            Some(Pending::Restore(id)) => self.restore(&id),
+            Some(Pending::Revert(dsl_file)) => self.revert(&dsl_file),
+            Some(Pending::DeleteVariant(name)) => match versions::delete_variant(&name) {
+                Ok(()) => self.update_log(format!("variant {} deleted", name).as_str()),
+                Err(e) => self.update_log(format!("fail to delete {}: {:#}", name, e).as_str()),
+            },
+            _ => {}
+        }
+    }
+}
+
+fn init_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>> {
+    crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
+    enable_raw_mode()?;
+    let backend = CrosstermBackend::new(io::stdout());
+    let mut terminal = Terminal::new(backend)?;
+    terminal.hide_cursor()?;
+    Ok(terminal)
+}
+
+fn reset_terminal() -> Result<()> {
+    disable_raw_mode()?;
+    crossterm::execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
+
+fn draw_file_list<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let items: Vec<ListItem> = editor
+        .files
+        .items
+        .iter()
+        .map(|i| {
+            let lines = vec![Spans::from(i.to_owned())];
+            ListItem::new(lines).style(Style::default())
+        })
+        .collect();
+    let items = List::new(items)
+        .block(
+            Block::default()
                 .borders(Borders::ALL)
                 .title("ACPI TABLES")
-                .title_alignment(Alignment::Center),
+                .title_alignment(Alignment::Center)
+                .border_style(focus_style(editor.focus == Focus::Files)),
         )
         .highlight_style(Style::default().bg(Color::LightGreen))
         .highlight_symbol(">> ");
@@ -450,13 +1820,25 @@ fn draw_file_list<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEdi
 }
 
 fn draw_modified_list<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    // the list shrinks as tables are reverted
+    let len = editor.modified.items.len();
+    if editor.modified.state.selected().is_some_and(|i| i >= len) {
+        editor.modified.state.select(len.checked_sub(1));
+    }
     let items: Vec<ListItem> = editor
         .modified
         .items
         .iter()
         .map(|i| {
             let lines = vec![Spans::from(i.to_owned())];
-            ListItem::new(lines).style(Style::default())
+            let style = if editor.excluded.contains(i) {
+                Style::default()
+                    .fg(Color::DarkGray)
+                    .add_modifier(Modifier::CROSSED_OUT)
+            } else {
+                Style::default()
+            };
+            ListItem::new(lines).style(style)
         })
         .collect();
     let items = List::new(items)
@@ -464,11 +1846,20 @@ fn draw_modified_list<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut Acp
             Block::default()
                 .borders(Borders::ALL)
                 .title("MODIFIED")
-                .title_alignment(Alignment::Center),
+                .title_alignment(Alignment::Center)
+                .border_style(focus_style(editor.focus == Focus::Modified)),
         )
         .highlight_style(Style::default().bg(Color::LightGreen))
         .highlight_symbol(">> ");
-    f.render_stateful_widget(items, area, &mut editor.files.state);
+    f.render_stateful_widget(items, area, &mut editor.modified.state);
+}
+
+fn focus_style(focused: bool) -> Style {
+    if focused {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    }
 }
 
 fn draw_search_box<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
@@ -478,9 +1869,379 @@ fn draw_search_box<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEd
     f.render_widget(search_box, area);
 }
 
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn draw_confirm_dialog<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let (title, lines) = match &editor.pending {
+        Some(pending) => (pending.title(), pending.lines()),
+        None => return,
+    };
+    let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let spans: Vec<Spans> = lines.into_iter().map(Spans::from).collect();
+    let dialog = Paragraph::new(spans).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .title_alignment(Alignment::Center),
+    );
+    let area = centered_rect(width, height, area);
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+fn draw_cmdline_panel<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let panel = match editor.cmdline.as_mut() {
+        Some(panel) => panel,
+        None => return,
+    };
+    let items: Vec<ListItem> = cmdline::PARAMS
+        .iter()
+        .zip(panel.args.iter())
+        .map(|(param, args)| {
+            let value = if args.is_empty() {
+                "(not set)".to_string()
+            } else {
+                args.join(" ")
+            };
+            ListItem::new(vec![
+                Spans::from(format!("{:<24}{}", param.key, value)),
+                Spans::from(Span::styled(
+                    format!("    {}", param.description),
+                    Style::default().fg(Color::DarkGray),
+                )),
+            ])
+        })
+        .collect();
+    let help = if panel.input.is_some() {
+        "Enter save, Esc cancel, empty removes, key= sets an empty value"
+    } else {
+        "Enter edit, x remove, Esc close"
+    };
+
+    let input_height = if panel.input.is_some() { 3 } else { 0 };
+    let height = cmdline::PARAMS.len() as u16 * 2 + 3 + input_height;
+    let area = centered_rect(area.width * 8 / 10, height, area);
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("KERNEL PARAMETERS: {}", panel.kernel))
+        .title_alignment(Alignment::Center);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(input_height),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+
+    let list = List::new(items)
+        .highlight_style(Style::default().bg(Color::LightGreen))
+        .highlight_symbol(">> ");
+    f.render_stateful_widget(list, chunks[0], &mut panel.params);
+    let title = format!("{} values", panel.selected().key);
+    if let Some(input) = panel.input.as_mut() {
+        input.set_block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(input.widget(), chunks[1]);
+    }
+    f.render_widget(Paragraph::new(help), chunks[2]);
+}
+
+/// Lines of a unified diff, colored.
+fn diff_spans(lines: &[String]) -> Vec<Spans<'_>> {
+    lines
+        .iter()
+        .map(|line| {
+            let color = if line.starts_with("+++") || line.starts_with("---") {
+                Color::White
+            } else if line.starts_with('+') {
+                Color::Green
+            } else if line.starts_with('-') {
+                Color::Red
+            } else if line.starts_with("@@") {
+                Color::Cyan
+            } else {
+                Color::Reset
+            };
+            Spans::from(Span::styled(line.clone(), Style::default().fg(color)))
+        })
+        .collect()
+}
+
+fn draw_history_panel<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let panel = match editor.history.as_mut() {
+        Some(panel) => panel,
+        None => return,
+    };
+    let count = panel.entries.len();
+    let items: Vec<ListItem> = panel
+        .entries
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(index, entry)| {
+            let mark = if panel.base == Some(index) { "*" } else { " " };
+            let tables: Vec<&str> = entry.tables.iter().map(|t| t.dsl_file.as_str()).collect();
+            ListItem::new(vec![
+                Spans::from(format!("{}#{:<4}{}", mark, index + 1, entry.timestamp)),
+                Spans::from(Span::styled(
+                    format!("      {} {}", entry.target, tables.join(" ")),
+                    Style::default().fg(Color::DarkGray),
+                )),
+            ])
+        })
+        .collect();
+    let lines = diff_spans(&panel.lines);
+
+    let area = centered_rect(area.width * 9 / 10, area.height * 8 / 10, area);
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("HISTORY: {} applies", count))
+        .title_alignment(Alignment::Center);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(inner);
+    let help = chunks[1];
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(34), Constraint::Min(1)])
+        .split(chunks[0]);
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(Style::default().bg(Color::LightGreen))
+        .highlight_symbol(">> ");
+    f.render_stateful_widget(list, chunks[0], &mut panel.list);
+    f.render_widget(Paragraph::new(lines).scroll((panel.scroll, 0)), chunks[1]);
+    f.render_widget(
+        Paragraph::new("j/k select, m mark to compare with, J/K scroll, Esc close"),
+        help,
+    );
+}
+
+fn draw_versions_panel<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let panel = match editor.versions.as_mut() {
+        Some(panel) => panel,
+        None => return,
+    };
+    let items: Vec<ListItem> = panel
+        .versions
+        .iter()
+        .map(|version| {
+            ListItem::new(vec![
+                Spans::from(format!("{} {}", version.id, version.message)),
+                Spans::from(Span::styled(
+                    format!("        {}", version.time),
+                    Style::default().fg(Color::DarkGray),
+                )),
+            ])
+        })
+        .collect();
+    let lines = diff_spans(&panel.lines);
+    let help = if panel.input.is_some() {
+        "Enter record, Esc cancel"
+    } else {
+        "j/k select, Enter return to version, c checkpoint, J/K scroll, Esc close"
+    };
+
+    let area = centered_rect(area.width * 9 / 10, area.height * 8 / 10, area);
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("VERSIONS")
+        .title_alignment(Alignment::Center);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let input_height = if panel.input.is_some() { 3 } else { 0 };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(input_height),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+    let (input_area, help_area) = (chunks[1], chunks[2]);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(40), Constraint::Min(1)])
+        .split(chunks[0]);
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(Style::default().bg(Color::LightGreen))
+        .highlight_symbol(">> ");
+    f.render_stateful_widget(list, chunks[0], &mut panel.list);
+    f.render_widget(Paragraph::new(lines).scroll((panel.scroll, 0)), chunks[1]);
+    if let Some(input) = panel.input.as_mut() {
+        input.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("checkpoint name"),
+        );
+        f.render_widget(input.widget(), input_area);
+    }
+    f.render_widget(Paragraph::new(help), help_area);
+}
+
+fn draw_variants_panel<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let current = editor.variant.clone().unwrap_or_default();
+    let panel = match editor.variants.as_mut() {
+        Some(panel) => panel,
+        None => return,
+    };
+    let items: Vec<ListItem> = panel
+        .variants
+        .iter()
+        .map(|name| {
+            let mark = if *name == current { "*" } else { " " };
+            ListItem::new(Spans::from(format!("{}{}", mark, name)))
+        })
+        .collect();
+    let mut lines = vec![Spans::from(format!(
+        "modified tables of {}:",
+        panel.selected()
+    ))];
+    lines.extend(panel.tables.iter().map(|t| Spans::from(format!("  {}", t))));
+    let help = match &panel.input {
+        Some(_) => "Enter create, Esc cancel",
+        None => "Enter switch, a switch and apply, n new, f new from firmware, d delete, Esc close",
+    };
+
+    let height = panel.variants.len().max(panel.tables.len() + 1) as u16 + 7;
+    let area = centered_rect(area.width * 8 / 10, height, area);
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("VARIANTS")
+        .title_alignment(Alignment::Center);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let input_height = if panel.input.is_some() { 3 } else { 0 };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(input_height),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+    let (input_area, help_area) = (chunks[1], chunks[2]);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(30), Constraint::Min(1)])
+        .split(chunks[0]);
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(Style::default().bg(Color::LightGreen))
+        .highlight_symbol(">> ");
+    f.render_stateful_widget(list, chunks[0], &mut panel.list);
+    f.render_widget(Paragraph::new(lines), chunks[1]);
+    if let Some((input, from_firmware)) = panel.input.as_mut() {
+        let title = if *from_firmware {
+            "new variant from the firmware tables"
+        } else {
+            "new variant from the current tables"
+        };
+        input.set_block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(input.widget(), input_area);
+    }
+    f.render_widget(Paragraph::new(help), help_area);
+}
+
+fn draw_diff<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let panel = match editor.diff.as_mut() {
+        Some(panel) => panel,
+        None => return,
+    };
+    let width = if panel.side_by_side {
+        area.width.saturating_sub(2) as usize
+    } else {
+        0
+    };
+    if panel.width != width {
+        let _ = panel.update(width);
+    }
+
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Spans> = panel
+        .diff
+        .lines
+        .iter()
+        .skip(panel.scroll)
+        .take(height)
+        .map(|(tag, line)| {
+            let style = match tag {
+                Tag::Header => Style::default().add_modifier(Modifier::BOLD),
+                Tag::Hunk => Style::default().fg(Color::Cyan),
+                Tag::Equal => Style::default(),
+                Tag::Delete => Style::default().fg(Color::Red),
+                Tag::Insert => Style::default().fg(Color::Green),
+                Tag::Replace => Style::default().fg(Color::Yellow),
+            };
+            Spans::from(Span::styled(line.clone(), style))
+        })
+        .collect();
+    let title = format!(
+        "DIFF {} (hunk {}/{}) n/N next/previous hunk, s side by side, Esc close",
+        panel.dsl_file,
+        panel.hunk + 1,
+        panel.diff.hunks.len()
+    );
+    let paragraph =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(paragraph, area);
+}
+
 fn draw_file_content<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
     let widget = editor.content.widget();
     f.render_widget(widget, area);
+
+    // follow the text area scrolling, its viewport is private
+    let height = area.height.saturating_sub(2) as usize;
+    let (row, _) = editor.content.cursor();
+    let top = editor.content_top;
+    editor.content_top = if row < top {
+        row
+    } else if top + height <= row {
+        row + 1 - height
+    } else {
+        top
+    };
+    editor.content_height = height;
+
+    // mark the changes from the firmware source on the left border
+    let len = editor.content.lines().len();
+    let rows = editor.content_top..(editor.content_top + height).min(len);
+    for change in editor.changes.iter() {
+        let (marked, mark, color) = match change.marker() {
+            Marker::Added => (change.new.clone(), "+", Color::Green),
+            Marker::Changed => (change.new.clone(), "~", Color::Yellow),
+            Marker::Deleted => (change.line(len)..change.line(len) + 1, "_", Color::Red),
+        };
+        for line in marked.filter(|line| rows.contains(line)) {
+            let y = area.y + 1 + (line - editor.content_top) as u16;
+            let marker = Paragraph::new(Span::styled(mark, Style::default().fg(color)));
+            f.render_widget(marker, Rect::new(area.x, y, 1, 1));
+        }
+    }
 }
 
 fn draw_log<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
@@ -488,12 +2249,49 @@ fn draw_log<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
     f.render_widget(widget, area);
 }
 
+fn draw_status<B: Backend>(f: &mut Frame<B>, area: Rect, editor: &mut AcpiEditor) {
+    let mode = match editor.mode {
+        Mode::Normal => "NORMAL",
+        Mode::Insert => "INSERT",
+        Mode::Search => "SEARCH",
+        Mode::Confirm => "CONFIRM",
+        Mode::Cmdline => "CMDLINE",
+        Mode::History => "HISTORY",
+        Mode::Versions => "VERSIONS",
+        Mode::Variants => "VARIANTS",
+        Mode::Diff => "DIFF",
+    };
+    let status = match &editor.task {
+        Some(task) => format!(
+            " {} | {} {}... (Esc to cancel)",
+            mode, SPINNER[editor.spinner], task.name
+        ),
+        None => format!(" {}", mode),
+    };
+    let status = match &editor.variant {
+        Some(variant) => format!("{} | variant: {}", status, variant),
+        None => status,
+    };
+    let status = match &editor.source {
+        Some(source) => format!("{} | offline: {}", status, source.display()),
+        None => status,
+    };
+    let status = Paragraph::new(status).style(Style::default().bg(Color::White).fg(Color::Black));
+    f.render_widget(status, area);
+}
+
 fn ui<B: Backend>(f: &mut Frame<B>, editor: &mut AcpiEditor) {
     let rect = f.size();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(rect);
+    let main = chunks[0];
+    let status = chunks[1];
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Length(20), Constraint::Min(1)])
-        .split(rect);
+        .split(main);
     let left_side = chunks[0];
     let right_side = chunks[1];
     let chunks = Layout::default()
@@ -512,9 +2310,31 @@ fn ui<B: Backend>(f: &mut Frame<B>, editor: &mut AcpiEditor) {
     draw_file_list(f, file_list_rect, editor);
     draw_modified_list(f, modified_file_rect, editor);
     draw_log(f, log, editor);
+    draw_status(f, status, editor);
 
     match editor.mode {
         Mode::Normal | Mode::Insert => draw_file_content(f, content, editor),
+        Mode::Confirm => {
+            draw_file_content(f, content, editor);
+            draw_confirm_dialog(f, rect, editor);
+        }
+        Mode::Cmdline => {
+            draw_file_content(f, content, editor);
+            draw_cmdline_panel(f, rect, editor);
+        }
+        Mode::History => {
+            draw_file_content(f, content, editor);
+            draw_history_panel(f, rect, editor);
+        }
+        Mode::Versions => {
+            draw_file_content(f, content, editor);
+            draw_versions_panel(f, rect, editor);
+        }
+        Mode::Variants => {
+            draw_file_content(f, content, editor);
+            draw_variants_panel(f, rect, editor);
+        }
+        Mode::Diff => draw_diff(f, content, editor),
         Mode::Search => {
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
@@ -530,16 +2350,28 @@ fn ui<B: Backend>(f: &mut Frame<B>, editor: &mut AcpiEditor) {
 
 fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io::Result<()> {
     loop {
+        editor.poll_task();
         terminal.draw(|f| ui(f, editor))?;
 
+        if !event::poll(TICK)? {
+            editor.tick();
+            continue;
+        }
+
         match editor.mode {
             Mode::Normal => match event::read()?.into() {
+                // keys for the focused file list, Ctrl ones still global
+                input if editor.focus != Focus::Content && !input.ctrl => editor.list_input(input),
                 // quit the application
                 Input {
                     key: Key::Char('c'),
                     ctrl: true,
                     ..
-                } => return Ok(()),
+                } if !editor.busy() => return Ok(()),
+                // cancel the running task
+                Input { key: Key::Esc, .. } => editor.cancel_task(),
+                // move the focus to the file lists
+                Input { key: Key::Tab, .. } => editor.cycle_focus(),
                 // file selection
                 Input { key: Key::Up, .. } => editor.previous_dsl_file(),
                 Input { key: Key::Down, .. } => editor.next_dsl_file(),
@@ -548,22 +2380,57 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     key: Key::Char('j'),
                     ..
                 } => editor.next_line(),
+                // edit the acpi kernel parameters
+                Input {
+                    key: Key::Char('k'),
+                    ctrl: true,
+                    ..
+                } => editor.open_cmdline(),
                 Input {
                     key: Key::Char('k'),
                     ..
                 } => editor.previous_line(),
+                // load and unload an SSDT at runtime
+                Input {
+                    key: Key::Char('l'),
+                    ctrl: true,
+                    ..
+                } => editor.load(),
+                Input {
+                    key: Key::Char('u'),
+                    ctrl: true,
+                    ..
+                } => editor.unload(),
                 Input {
                     key: Key::Char('l'),
                     ..
                 } => editor.next_char(),
+                // hot-patch the method under the cursor
+                Input {
+                    key: Key::Char('t'),
+                    ctrl: true,
+                    ..
+                } => editor.replace_method(),
                 Input {
                     key: Key::Char('h'),
                     ..
                 } => editor.previous_char(),
+                // switch between variants of the tables
+                Input {
+                    key: Key::Char('b'),
+                    ctrl: true,
+                    ..
+                } => editor.open_variants(),
                 Input {
                     key: Key::Char('b'),
                     ..
                 } => editor.previous_word(),
+                // go back to an earlier version of the tables
+                Input {
+                    key: Key::Char('g'),
+                    ctrl: true,
+                    ..
+                } => editor.open_versions(),
                 Input {
                     key: Key::Char('g'),
                     ..
@@ -571,21 +2438,21 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                 Input {
                     key: Key::Char('G'),
                     ..
-                } => editor.to_bottom(),
+                } => editor.goto_bottom(),
                 Input {
                     key: Key::Char('0'),
                     ..
-                } => editor.to_line_head(),
+                } => editor.goto_line_head(),
                 Input {
                     key: Key::Char('$'),
                     ..
-                } => editor.to_line_end(),
+                } => editor.goto_line_end(),
                 Input {
                     key: Key::PageUp, ..
-                } => editor.to_previous_page(),
+                } => editor.goto_previous_page(),
                 Input {
                     key: Key::PageDown, ..
-                } => editor.to_next_page(),
+                } => editor.goto_next_page(),
                 // search
                 Input {
                     key: Key::Char('n'),
@@ -614,6 +2481,11 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     ..
                 } => editor.insert_new_line_up(),
                 // edit
+                Input {
+                    key: Key::Char('d'),
+                    ctrl: true,
+                    ..
+                } => editor.apply(true),
                 Input {
                     key: Key::Char('d'),
                     ..
@@ -622,6 +2494,12 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     key: Key::Char('w'),
                     ..
                 } => editor.try_delete_word(),
+                // undo all edits to the selected table
+                Input {
+                    key: Key::Char('x'),
+                    ctrl: true,
+                    ..
+                } => editor.confirm_revert(),
                 Input {
                     key: Key::Char('x'),
                     ..
@@ -630,6 +2508,19 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     key: Key::Char('u'),
                     ..
                 } => editor.undo(),
+                // changes from the firmware source
+                Input {
+                    key: Key::Char(']'),
+                    ..
+                } => editor.goto_change(false),
+                Input {
+                    key: Key::Char('['),
+                    ..
+                } => editor.goto_change(true),
+                Input {
+                    key: Key::Char('U'),
+                    ..
+                } => editor.revert_change(),
                 // into search mode
                 Input {
                     key: Key::Char('/'),
@@ -640,14 +2531,43 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     key: Key::Char('a'),
                     ctrl: true,
                     ..
-                } => editor.apply(),
+                } => editor.apply(false),
+                // apply as efi variables
+                Input {
+                    key: Key::Char('v'),
+                    ctrl: true,
+                    ..
+                } => editor.apply_efivar(),
+                // dump the firmware tables again
+                Input {
+                    key: Key::Char('r'),
+                    ctrl: true,
+                    ..
+                } => editor.redump(),
+                // diff the selected table against the firmware
+                Input {
+                    key: Key::Char('f'),
+                    ctrl: true,
+                    ..
+                } => editor.open_diff(),
+                // browse past applies
+                Input {
+                    key: Key::Char('y'),
+                    ctrl: true,
+                    ..
+                } => editor.open_history(),
+                // export the modified tables
+                Input {
+                    key: Key::Char('e'),
+                    ctrl: true,
+                    ..
+                } => editor.export(),
                 _ => {}
             },
             Mode::Insert => {
                 if let Event::Key(key) = event::read()? {
-                    match key.code {
-                        KeyCode::Esc => editor.switch_mode(Mode::Normal),
-                        _ => {}
+                    if key.code == KeyCode::Esc {
+                        editor.switch_mode(Mode::Normal);
                     }
                     editor.insert(key);
                 }
@@ -662,51 +2582,47 @@ fn start<B: Backend>(terminal: &mut Terminal<B>, editor: &mut AcpiEditor) -> io:
                     editor.search_input(key);
                 }
             }
+            Mode::Cmdline => {
+                if let Event::Key(key) = event::read()? {
+                    editor.cmdline_input(key);
+                }
+            }
+            Mode::History => {
+                if let Event::Key(key) = event::read()? {
+                    editor.history_input(key);
+                }
+            }
+            Mode::Versions => {
+                if let Event::Key(key) = event::read()? {
+                    editor.versions_input(key);
+                }
+            }
+            Mode::Variants => {
+                if let Event::Key(key) = event::read()? {
+                    editor.variants_input(key);
+                }
+            }
+            Mode::Diff => {
+                if let Event::Key(key) = event::read()? {
+                    editor.diff_input(key);
+                }
+            }
+            Mode::Confirm => {
+                if let Event::Key(key) = event::read()? {
+                    match key.code {
+                        KeyCode::Char(c) => editor.answer(c),
+                        KeyCode::Esc => editor.cancel(),
+                        _ => {}
+                    }
+                }
+            }
         }
     }
 }
 
-fn check_executable(executable: &str) {
-    let output = Command::new("which")
-        .arg(executable)
-        .output()
-        .expect(format!("fail to check {}!", executable).as_str());
-    if !output.status.success() {
-        eprintln!("{}", format!("{} not found!", executable));
-        exit(1);
-    }
-}
-
-fn check_user() {
-    let output = Command::new("id")
-        .arg("-u")
-        .output()
-        .expect("fail to check user!");
-    if !output.status.success() {
-        eprintln!("check user failed");
-    } else {
-        if String::from_utf8(output.stdout.to_owned())
-            .unwrap()
-            .trim_end()
-            != String::from("0")
-        {
-            eprintln!("acpied must be run as root!");
-            exit(1);
-        }
-    }
-}
-
-fn check_prerequisites() {
-    check_user();
-    check_executable("grubby");
-    check_executable("acpidump");
-    check_executable("acpixtract");
-    check_executable("iasl");
-}
-
-pub fn run() -> Result<()> {
-    check_prerequisites();
-    let mut editor = AcpiEditor::new();
+pub fn run(redump: bool) -> Result<()> {
+    crate::check_prerequisites(redump);
+    let mut editor = AcpiEditor::new(redump);
     let mut terminal = init_terminal()?;
     let result = start(&mut terminal, &mut editor);
     reset_terminal()?;
@@ -0,0 +1,233 @@
+use crate::config;
+use crate::efivar;
+use crate::journal;
+use crate::table::TableHeader;
+use crate::workspace;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A table as found in sysfs or in the applied directory.
+struct Table {
+    name: String,
+    header: TableHeader,
+    hash: String,
+}
+
+fn read_table(path: &Path) -> Result<Table> {
+    let data = fs::read(path)?;
+    Ok(Table {
+        name: path.file_name().unwrap().to_string_lossy().to_string(),
+        header: TableHeader::parse(&data)?,
+        hash: workspace::sha256(&data),
+    })
+}
+
+/// Tables the running kernel exposes, including the dynamically loaded ones
+/// such as SSDTs from EFI variables, or only those if `dynamic_only`.
+fn running_tables(dynamic_only: bool) -> Result<Vec<Table>> {
+    let dir = config::get().system_path(workspace::FIRMWARE_TABLES);
+    let mut tables = vec![];
+    let dirs = if dynamic_only {
+        vec![dir.join("dynamic")]
+    } else {
+        vec![dir.clone(), dir.join("dynamic")]
+    };
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_file() {
+                if let Ok(table) = read_table(&path) {
+                    tables.push(table);
+                }
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// Tables upgraded or installed from the initrd according to kernel log lines
+/// such as `ACPI: Table Upgrade: override [SSDT-OEMID -TABLEID ]`, as
+/// (action, signature, oem table id).
+pub fn upgrades(log: &str) -> Vec<(String, String, String)> {
+    let mut upgrades = vec![];
+    for line in log.lines() {
+        let rest = match line.split_once("Table Upgrade: ") {
+            Some((_, rest)) => rest,
+            None => continue,
+        };
+        let (action, rest) = match rest.split_once(" [") {
+            Some(split) => split,
+            None => continue,
+        };
+        // the ids are printed with fixed widths, "%4.4s-%6.6s-%8.8s"
+        let ids: Vec<char> = rest.chars().take(20).collect();
+        if ids.len() == 20 {
+            let field = |range: std::ops::Range<usize>| {
+                ids[range].iter().collect::<String>().trim_end().to_string()
+            };
+            upgrades.push((action.to_string(), field(0..4), field(12..20)));
+        }
+    }
+    upgrades
+}
+
+/// Kernel log read from `dmesg` if given, else from the running kernel.
+/// Variables the kernel loaded SSDTs from according to kernel log lines such
+/// as `efi: loading SSDT from variable acpied-<guid>`.
+fn efivar_loads(log: &str) -> Vec<String> {
+    log.lines()
+        .filter_map(|line| line.split_once("loading SSDT from variable "))
+        .filter_map(|(_, rest)| rest.split_whitespace().next())
+        .map(String::from)
+        .collect()
+}
+
+pub fn kernel_log(dmesg: Option<&PathBuf>) -> Result<Option<String>> {
+    if let Some(path) = dmesg {
+        let log = fs::read(path).with_context(|| format!("fail to read {}", path.display()))?;
+        return Ok(Some(String::from_utf8_lossy(&log).to_string()));
+    }
+    if !config::get().is_live() {
+        return Ok(None);
+    }
+    match Command::new("dmesg").output() {
+        Ok(output) if output.status.success() => {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Report for each table of the last apply whether the running kernel uses
+/// it, from the tables it exposes and from the kernel log, read from `dmesg`
+/// if given. Tables applied as EFI variables are looked up among the
+/// dynamically loaded tables and the SSDT loads of the kernel log.
+pub fn run(dmesg: Option<&PathBuf>) -> Result<()> {
+    let applied_dir = workspace::applied_dir();
+    let mut applied = vec![];
+    if let Ok(entries) = fs::read_dir(&applied_dir) {
+        for entry in entries {
+            applied.push(read_table(&entry?.path())?);
+        }
+    }
+    if applied.is_empty() {
+        bail!("no applied tables recorded in {}", applied_dir.display());
+    }
+    applied.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let efivar = journal::entries()?
+        .last()
+        .is_some_and(|entry| entry.target == "efivar");
+    let running = running_tables(efivar)?;
+    let log = kernel_log(dmesg)?;
+    let upgrades = log.as_deref().map(upgrades).unwrap_or_default();
+    let efivar_loads = log.as_deref().map(efivar_loads).unwrap_or_default();
+    if log.is_none() {
+        println!("kernel log not available, pass --dmesg <file> to check it");
+    }
+
+    let mut failed = 0;
+    for table in applied.iter() {
+        let h = &table.header;
+        println!(
+            "{}: {} {} {} oem rev {:#x} checksum {:#04x}",
+            table.name, h.signature, h.oem_id, h.oem_table_id, h.oem_revision, h.checksum
+        );
+
+        let same_id = |t: &&Table| {
+            t.header.signature == h.signature && t.header.oem_table_id == h.oem_table_id
+        };
+        match running.iter().find(|t| t.hash == table.hash) {
+            Some(t) => println!("  running as {}", t.name),
+            None => {
+                failed += 1;
+                match running.iter().find(same_id) {
+                    Some(t) => println!(
+                        "  NOT running, {} has oem rev {:#x} checksum {:#04x}",
+                        t.name, t.header.oem_revision, t.header.checksum
+                    ),
+                    None => println!("  NOT running, no table with this signature and id"),
+                }
+            }
+        }
+
+        if log.is_some() && efivar {
+            let stem = table.name.strip_suffix(".aml").unwrap_or(&table.name);
+            let variable = efivar::variable_name(&format!("{}.dsl", stem));
+            if efivar_loads.contains(&variable) {
+                println!("  kernel log: loaded from efi variable {}", variable);
+            } else {
+                println!("  kernel log: efi variable {} not loaded", variable);
+            }
+        } else if log.is_some() {
+            match upgrades
+                .iter()
+                .find(|(_, sig, id)| *sig == h.signature && *id == h.oem_table_id)
+            {
+                Some((action, _, _)) => println!("  kernel log: table upgrade {}", action),
+                None => println!("  kernel log: no table upgrade"),
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{} of {} applied tables not in effect",
+            failed,
+            applied.len()
+        );
+    }
+    println!("all {} applied tables in effect", applied.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_log_upgrades() {
+        let log = "\
+[    0.000000] ACPI: Table Upgrade: override [DSDT-ALASKA-A M I   ]
+[    0.000000] ACPI: DSDT 0x000000007B8A2000 Physical table override, new table: 0x0000000079FFE000
+[    0.000000] ACPI: Table Upgrade: install [SSDT-TEST  -NEWTABLE]
+[    0.000000] ACPI: Table Upgrade: override [SSDT-SHORT]
+";
+        let found = upgrades(log);
+        let expected: Vec<(String, String, String)> = [
+            ("override", "DSDT", "A M I"),
+            ("install", "SSDT", "NEWTABLE"),
+        ]
+        .iter()
+        .map(|(a, s, i)| (a.to_string(), s.to_string(), i.to_string()))
+        .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn kernel_log_efivar_loads() {
+        let log = "\
+[    0.912345] efi: loading SSDT from variable acpied-a94706be-be99-5f5b-b013-c5345e0a79ed
+[    0.912400] ACPI: Table Upgrade: install [SSDT-TEST  -NEWTABLE]
+";
+        assert_eq!(
+            efivar_loads(log),
+            vec!["acpied-a94706be-be99-5f5b-b013-c5345e0a79ed".to_string()]
+        );
+        assert!(efivar_loads("[    0.000000] efi: EFI v2.70\n").is_empty());
+    }
+
+    #[test]
+    fn no_upgrades() {
+        assert!(
+            upgrades("[    0.000000] ACPI: Early table checksum verification disabled\n")
+                .is_empty()
+        );
+    }
+}
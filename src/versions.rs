@@ -0,0 +1,266 @@
+use crate::config;
+use crate::journal;
+use crate::workspace;
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Git repository holding the versions of the modified tables, the modified
+/// directory being its work tree. Each variant of the tables is a branch.
+pub fn versions_dir() -> PathBuf {
+    workspace::root().join("versions")
+}
+
+/// Run git on the versions repository with `work_tree` as work tree and
+/// return its output.
+fn git_in(work_tree: &Path, args: &[&str]) -> Result<String> {
+    let user = journal::user();
+    let output = Command::new(&config::get().tools.git)
+        .arg("--git-dir")
+        .arg(versions_dir())
+        .arg("--work-tree")
+        .arg(work_tree)
+        .args(["-c", "commit.gpgsign=false", "-c", "core.autocrlf=false"])
+        .args(args)
+        .env("GIT_AUTHOR_NAME", &user)
+        .env("GIT_AUTHOR_EMAIL", "acpied@localhost")
+        .env("GIT_COMMITTER_NAME", &user)
+        .env("GIT_COMMITTER_EMAIL", "acpied@localhost")
+        .output()
+        .context("fail to execute git")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            stderr.trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Variant the workspace starts with.
+pub const MAIN: &str = "main";
+
+fn git(args: &[&str]) -> Result<String> {
+    git_in(&workspace::modified_dir(), args)
+}
+
+/// Start the versions of a workspace, the firmware tables as first version
+/// and the edits made so far, if any, on top. Does nothing if already done.
+pub fn init(progress: &dyn Fn(&str)) -> Result<()> {
+    if versions_dir().exists() {
+        return Ok(());
+    }
+    let result = (|| -> Result<()> {
+        git(&["init", "-q", "-b", MAIN])?;
+        let origin = workspace::origin_dir();
+        let mut args = vec!["add".to_string(), "--".to_string()];
+        args.extend(
+            workspace::tables()?
+                .into_iter()
+                .filter(|dsl_file| origin.join(dsl_file).exists()),
+        );
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        git_in(&origin, &args)?;
+        git(&["commit", "-q", "--allow-empty", "-m", "firmware dump"])?;
+        checkpoint("edits before versioning")?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(versions_dir());
+    }
+    result.context("fail to start the versions of the workspace")?;
+    progress(&format!("versions kept in {}", versions_dir().display()));
+    Ok(())
+}
+
+/// Record the modified tables as a new version named `message`. Returns its
+/// id, or `None` if nothing changed since the last version.
+pub fn checkpoint(message: &str) -> Result<Option<String>> {
+    git(&["add", "-A"])?;
+    if git(&["status", "--porcelain"])?.trim().is_empty() {
+        return Ok(None);
+    }
+    git(&["commit", "-q", "-m", message])?;
+    Ok(Some(current()?))
+}
+
+/// A recorded state of the modified tables.
+pub struct Version {
+    pub id: String,
+    pub time: String,
+    pub message: String,
+    /// Tables changed from the version before.
+    pub tables: Vec<String>,
+}
+
+impl Version {
+    /// One line summary.
+    pub fn title(&self) -> String {
+        let tables = if self.tables.len() > 4 {
+            format!("{} tables", self.tables.len())
+        } else {
+            self.tables.join(" ")
+        };
+        format!("{}  {}  {}  {}", self.id, self.time, self.message, tables)
+    }
+}
+
+/// Id of the latest version.
+pub fn current() -> Result<String> {
+    Ok(git(&["rev-parse", "--short", "HEAD"])?.trim().to_string())
+}
+
+/// Versions, newest first, only those changing `dsl_file` if given.
+pub fn log(dsl_file: Option<&str>) -> Result<Vec<Version>> {
+    let mut args = vec!["log", "--format=%x00%h%x09%ci%x09%s", "--name-only"];
+    if let Some(dsl_file) = dsl_file {
+        args.extend(["--", dsl_file]);
+    }
+    let output = git(&args)?;
+    let mut versions = vec![];
+    for record in output.split('\0').filter(|r| !r.trim().is_empty()) {
+        let mut lines = record.lines();
+        let header = lines.next().unwrap_or_default();
+        let mut fields = header.splitn(3, '\t');
+        let mut field = || fields.next().unwrap_or_default().to_string();
+        versions.push(Version {
+            id: field(),
+            time: field(),
+            message: field(),
+            tables: lines.filter(|l| !l.is_empty()).map(String::from).collect(),
+        });
+    }
+    Ok(versions)
+}
+
+/// Changes made by a version, to `dsl_file` only if given.
+pub fn show(id: &str, dsl_file: Option<&str>) -> Result<Vec<String>> {
+    let mut args = vec!["show", "--format=%h %ci %s", id];
+    if let Some(dsl_file) = dsl_file {
+        args.extend(["--", dsl_file]);
+    }
+    Ok(git(&args)?.lines().map(String::from).collect())
+}
+
+/// Bring the modified tables back to version `id`, as a new version so the
+/// current state stays in the history. Returns the id of the new version.
+pub fn restore(id: &str) -> Result<Option<String>> {
+    let commit = git(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        "--end-of-options",
+        &format!("{}^{{commit}}", id),
+    ])
+    .map_err(|_| anyhow!("no version {}", id))?;
+    checkpoint(&format!("before returning to {}", id))?;
+    git(&["read-tree", "-u", "--reset", commit.trim()])?;
+    checkpoint(&format!("return to {}", id))
+}
+
+/// Variant the modified tables belong to.
+pub fn variant() -> Result<String> {
+    Ok(git(&["symbolic-ref", "--short", "HEAD"])?
+        .trim()
+        .to_string())
+}
+
+/// Names of the variants.
+pub fn variants() -> Result<Vec<String>> {
+    Ok(
+        git(&["for-each-ref", "--format=%(refname:short)", "refs/heads"])?
+            .lines()
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Tables of a variant that differ from the firmware dump.
+pub fn variant_tables(name: &str) -> Result<Vec<String>> {
+    if variant()? == name {
+        return workspace::modified();
+    }
+    let base = firmware_version()?;
+    let branch = format!("refs/heads/{}", name);
+    Ok(git(&["diff", "--name-only", &base, &branch])?
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// The first version, holding the firmware tables.
+fn firmware_version() -> Result<String> {
+    let roots = git(&["rev-list", "--max-parents=0", "HEAD"])?;
+    roots
+        .lines()
+        .last()
+        .map(String::from)
+        .ok_or_else(|| anyhow!("no firmware version"))
+}
+
+/// Whether any variant moved on from the firmware tables, so deleting the
+/// versions would lose edits even if the current tables are unmodified.
+pub fn holds_edits() -> Result<bool> {
+    if !versions_dir().exists() {
+        return Ok(false);
+    }
+    let base = firmware_version()?;
+    Ok(
+        git(&["for-each-ref", "--format=%(objectname)", "refs/heads"])?
+            .lines()
+            .any(|id| id != base),
+    )
+}
+
+fn check_variant(name: &str, exists: bool) -> Result<()> {
+    if git(&["check-ref-format", "--branch", name]).is_err() || name.starts_with('-') {
+        bail!("invalid variant name {}", name);
+    }
+    let found = variants()?.iter().any(|v| v == name);
+    if found && !exists {
+        bail!("variant {} already exists", name);
+    }
+    if !found && exists {
+        bail!("no variant {}, see `acpied variant`", name);
+    }
+    Ok(())
+}
+
+/// Create variant `name` from the current tables, or from the firmware tables
+/// if `from_firmware` is set, and switch to it.
+pub fn new_variant(name: &str, from_firmware: bool) -> Result<()> {
+    check_variant(name, false)?;
+    checkpoint(&format!("before creating variant {}", name))?;
+    let base = if from_firmware {
+        firmware_version()?
+    } else {
+        "HEAD".to_string()
+    };
+    git(&["branch", name, &base])?;
+    switch_variant(name)
+}
+
+/// Keep the current tables in their variant and bring in those of `name`.
+pub fn switch_variant(name: &str) -> Result<()> {
+    check_variant(name, true)?;
+    checkpoint(&format!("before switching to {}", name))?;
+    let branch = format!("refs/heads/{}", name);
+    git(&["read-tree", "-u", "--reset", &branch])?;
+    git(&["symbolic-ref", "HEAD", &branch])?;
+    Ok(())
+}
+
+/// Drop variant `name` and its versions.
+pub fn delete_variant(name: &str) -> Result<()> {
+    check_variant(name, true)?;
+    if variant()? == name {
+        bail!(
+            "{} is the current variant, switch to another one first",
+            name
+        );
+    }
+    git(&["branch", "-D", name])?;
+    Ok(())
+}
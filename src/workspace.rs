@@ -0,0 +1,574 @@
+use crate::apply::{self, Validation};
+use crate::config;
+use crate::iasl;
+use crate::table::TableHeader;
+use crate::verify;
+use crate::versions;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Tables exposed by the running kernel.
+pub const FIRMWARE_TABLES: &str = "/sys/firmware/acpi/tables";
+
+/// Tables of an opened workspace.
+pub struct Workspace {
+    pub tables: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+/// Root of the workspace.
+pub fn root() -> PathBuf {
+    config::get().workspace.clone()
+}
+
+/// Tables as dumped from the firmware.
+pub fn origin_dir() -> PathBuf {
+    root().join("origin")
+}
+
+/// Tables as edited.
+pub fn modified_dir() -> PathBuf {
+    root().join("modified")
+}
+
+/// Scratch directory for compiled tables.
+pub fn staging_dir() -> PathBuf {
+    root().join("staging")
+}
+
+/// Tables as shipped by the firmware, kept across re-dumps so diffs stay
+/// against the real firmware once our overrides are running.
+pub fn pristine_dir() -> PathBuf {
+    root().join("pristine")
+}
+
+/// AML of the last apply.
+pub fn applied_dir() -> PathBuf {
+    root().join("applied")
+}
+
+fn fingerprint_file() -> PathBuf {
+    root().join("firmware")
+}
+
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hashes of the AML installed by the last apply.
+fn applied_hashes() -> HashSet<String> {
+    let mut hashes = HashSet::new();
+    if let Ok(files) = table_files(&applied_dir(), |_| true) {
+        for path in files {
+            if let Ok(data) = fs::read(&path) {
+                hashes.insert(sha256(&data));
+            }
+        }
+    }
+    hashes
+}
+
+/// Keep the AML just installed, to recognize it among the running tables
+/// after a reboot.
+pub fn record_applied(validation: &Validation) -> Result<()> {
+    let dir = applied_dir();
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+    for t in validation.tables.iter() {
+        fs::copy(
+            &t.aml,
+            dir.join(format!("{}.aml", apply::stem(&t.dsl_file))),
+        )?;
+    }
+    Ok(())
+}
+
+/// Tables the kernel log reports as upgraded from the initrd, as (signature,
+/// oem table id). This catches overrides whose AML is not in the applied
+/// directory, such as those installed by hand or by another tool.
+fn upgraded_ids() -> Vec<(String, String)> {
+    let log = match verify::kernel_log(None) {
+        Ok(Some(log)) => log,
+        _ => return vec![],
+    };
+    verify::upgrades(&log)
+        .into_iter()
+        .map(|(_, signature, table_id)| (signature, table_id))
+        .collect()
+}
+
+/// Whether the running table `data` is an override rather than the
+/// firmware's own.
+fn is_override(data: &[u8], applied: &HashSet<String>, upgraded: &[(String, String)]) -> bool {
+    applied.contains(&sha256(data))
+        || TableHeader::parse(data).is_ok_and(|header| {
+            upgraded.iter().any(|(signature, table_id)| {
+                *signature == header.signature && *table_id == header.oem_table_id
+            })
+        })
+}
+
+/// Running tables that are overrides rather than the firmware's own.
+pub fn overrides() -> Result<Vec<String>> {
+    let applied = applied_hashes();
+    let upgraded = upgraded_ids();
+    let mut overrides = vec![];
+    for path in table_files(&config::get().system_path(FIRMWARE_TABLES), |_| true)? {
+        if is_override(&fs::read(&path)?, &applied, &upgraded) {
+            overrides.push(path.file_name().unwrap().to_string_lossy().to_string());
+        }
+    }
+    Ok(overrides)
+}
+
+/// Name and hash of each table exposed by the running firmware.
+fn firmware_hashes() -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    for entry in fs::read_dir(config::get().system_path(FIRMWARE_TABLES))? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        hashes.insert(name, sha256(&fs::read(&path)?));
+    }
+    Ok(hashes)
+}
+
+/// One line per table exposed by the running firmware, with its hash.
+fn firmware_fingerprint() -> Result<String> {
+    let lines: Vec<String> = firmware_hashes()?
+        .into_iter()
+        .map(|(name, hash)| format!("{} {}", name, hash))
+        .collect();
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Whether the workspace was dumped from the firmware we are running on.
+/// Tables replaced by our own overrides since the dump do not count as a
+/// firmware change.
+fn matches_firmware() -> bool {
+    let (saved, current) = match (fs::read_to_string(fingerprint_file()), firmware_hashes()) {
+        (Ok(saved), Ok(current)) => (saved, current),
+        _ => return false,
+    };
+    let saved: BTreeMap<&str, &str> = saved
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+    let applied = applied_hashes();
+    saved.len() == current.len()
+        && current.iter().all(|(name, hash)| {
+            saved.get(name.as_str()) == Some(&hash.as_str()) || applied.contains(hash)
+        })
+}
+
+/// Keep the firmware baseline up to date after a dump. Tables running as
+/// overrides, ours or those the kernel log reports, are put back to their
+/// firmware version in the origin directory, so edits are still diffed
+/// against the real firmware; the others are the firmware's own and become
+/// the baseline.
+fn update_baseline(progress: &dyn Fn(&str)) -> Result<()> {
+    let applied = applied_hashes();
+    let upgraded = upgraded_ids();
+    let pristine = pristine_dir();
+    fs::create_dir_all(&pristine)?;
+    for dat in table_files(&origin_dir(), |p| p.extension().is_some_and(|e| e == "dat"))? {
+        let name = dat.file_name().unwrap().to_owned();
+        let baseline = pristine.join(&name);
+        let data = fs::read(&dat)?;
+        let stem = dat.file_stem().unwrap().to_string_lossy().to_string();
+
+        if !is_override(&data, &applied, &upgraded) {
+            if fs::read(&baseline).ok().is_some_and(|b| b != data) {
+                progress(&format!(
+                    "{} changed in the firmware, baseline updated",
+                    stem
+                ));
+            }
+            fs::write(&baseline, data)?;
+            continue;
+        }
+        if !baseline.exists() {
+            progress(&format!(
+                "{} is overridden and has no firmware baseline, diffs are against the override",
+                stem
+            ));
+            continue;
+        }
+        progress(&format!(
+            "{} is overridden, diffing against the firmware baseline",
+            stem
+        ));
+        fs::copy(&baseline, &dat)?;
+        iasl::disassemble(&dat, &origin_dir().join(&stem), &stem)?;
+    }
+    Ok(())
+}
+
+/// Dump the running firmware tables into a fresh workspace and return the
+/// dsl files available for editing.
+pub fn init(progress: &dyn Fn(&str)) -> Result<Vec<String>> {
+    if !config::get().is_live() {
+        let tables_dir = config::get().system_path(FIRMWARE_TABLES);
+        populate(&table_files(&tables_dir, |_| true)?)?;
+        update_baseline(progress)?;
+        fs::write(fingerprint_file(), firmware_fingerprint()?)?;
+        return tables();
+    }
+
+    reset()?;
+    let tools = &config::get().tools;
+    let output = Command::new("bash")
+        .arg(&tools.init_script)
+        .arg(root())
+        .env("ACPIDUMP", &tools.acpidump)
+        .env("ACPIXTRACT", &tools.acpixtract)
+        .env("IASL", &tools.iasl)
+        .output()
+        .context("fail to execute script!")?;
+    if !output.status.success() {
+        bail!("script executed with error code!");
+    }
+    update_baseline(progress)?;
+    fs::write(fingerprint_file(), firmware_fingerprint()?)?;
+    tables()
+}
+
+/// Files of a directory of binary tables accepted by `filter`.
+fn table_files(dir: &Path, filter: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("fail to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() && filter(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Files of the workspace kept when it is dumped again or moved aside.
+const KEPT: [&str; 4] = ["pristine", "applied", "journal.jsonl", "history"];
+
+/// Empty the workspace for a new dump, keeping only `KEPT`.
+fn reset() -> Result<()> {
+    let workspace = root();
+    // the versions start again from the new dump, their first version
+    // holding the firmware tables
+    let versions = versions::versions_dir();
+    if versions.exists() {
+        fs::remove_dir_all(&versions)
+            .with_context(|| format!("fail to remove {}", versions.display()))?;
+    }
+    if workspace.exists() {
+        for entry in fs::read_dir(&workspace)? {
+            let path = entry?.path();
+            if KEPT
+                .iter()
+                .any(|k| path.file_name().is_some_and(|n| n == *k))
+            {
+                continue;
+            }
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    fs::create_dir_all(origin_dir())?;
+    fs::create_dir_all(modified_dir())?;
+    Ok(())
+}
+
+/// Disassemble the binary tables in the origin directory and copy the sources
+/// to the modified directory for editing. Like `iasl -d *.dat` in acpied-init,
+/// tables iasl cannot handle are left out of the editable set.
+fn disassemble_origin() -> Result<()> {
+    for dat in table_files(&origin_dir(), |p| p.extension().is_some_and(|e| e == "dat"))? {
+        let stem = dat.file_stem().unwrap().to_string_lossy().to_string();
+        if let Ok(dsl) = iasl::disassemble(&dat, &origin_dir().join(&stem), &stem) {
+            fs::copy(&dsl, modified_dir().join(dsl.file_name().unwrap()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Populate a fresh workspace from binary tables, naming them the way
+/// acpixtract does.
+fn populate(files: &[PathBuf]) -> Result<()> {
+    reset()?;
+    for path in files.iter() {
+        let stem = path.file_stem().unwrap().to_string_lossy().to_lowercase();
+        fs::copy(path, origin_dir().join(format!("{}.dat", stem)))?;
+    }
+    disassemble_origin()
+}
+
+fn source_file() -> PathBuf {
+    root().join("source")
+}
+
+/// Dump or directory the workspace was imported from, if it was not dumped
+/// from a running system.
+pub fn source() -> Option<PathBuf> {
+    fs::read_to_string(source_file())
+        .ok()
+        .map(|s| PathBuf::from(s.trim_end()))
+}
+
+/// Whether the workspace holds imported tables rather than the running
+/// firmware; such a workspace can be exported but not applied.
+pub fn is_offline() -> bool {
+    source_file().exists()
+}
+
+/// Import tables from an acpidump text file or a directory of .dat or .aml
+/// files into a fresh workspace.
+fn import_tables(source: &Path) -> Result<Vec<String>> {
+    if source.is_dir() {
+        let files = table_files(source, |p| {
+            p.extension().is_some_and(|e| e == "dat" || e == "aml")
+        })?;
+        if files.is_empty() {
+            bail!("no .dat or .aml files in {}", source.display());
+        }
+        populate(&files)?;
+    } else {
+        reset()?;
+        let output = Command::new(&config::get().tools.acpixtract)
+            .arg("-a")
+            .arg(source)
+            .current_dir(origin_dir())
+            .output()
+            .context("fail to execute acpixtract")?;
+        if !output.status.success() {
+            bail!("acpixtract could not extract {}", source.display());
+        }
+        disassemble_origin()?;
+    }
+    fs::write(source_file(), format!("{}\n", source.display()))?;
+    tables()
+}
+
+/// Move the workspace aside if it holds edits that would be lost, in the
+/// current tables or in any variant.
+fn move_aside(progress: &dyn Fn(&str)) -> Result<()> {
+    if !modified_dir().is_dir() {
+        return Ok(());
+    }
+    // a broken versions repository is kept rather than deleted
+    if modified()?.is_empty() && !versions::holds_edits().unwrap_or(true) {
+        return Ok(());
+    }
+    let workspace = root();
+    let backup = PathBuf::from(format!(
+        "{}.{}",
+        workspace.display(),
+        Local::now().format("%Y%m%d%H%M%S")
+    ));
+    fs::rename(&workspace, &backup)?;
+    fs::create_dir_all(&workspace)?;
+    for kept in KEPT {
+        if backup.join(kept).exists() {
+            fs::rename(backup.join(kept), workspace.join(kept))?;
+        }
+    }
+    progress(&format!("previous edits kept in {}", backup.display()));
+    Ok(())
+}
+
+/// Report the running tables that are our overrides.
+fn report_overrides(progress: &dyn Fn(&str)) {
+    if let Ok(overrides) = overrides() {
+        if !overrides.is_empty() {
+            progress(&format!("running overrides: {}", overrides.join(", ")));
+        }
+    }
+}
+
+/// Keep versions of the edits; the workspace is still usable without them.
+fn start_versions(progress: &dyn Fn(&str)) {
+    if let Err(e) = versions::init(progress) {
+        progress(&format!("{:#}", e));
+    }
+}
+
+fn reuse(progress: &dyn Fn(&str)) -> Result<Workspace> {
+    progress(&format!("reusing workspace {}", root().display()));
+    if source().is_none() {
+        report_overrides(progress);
+    }
+    start_versions(progress);
+    Ok(Workspace {
+        tables: tables()?,
+        modified: modified()?,
+    })
+}
+
+/// Open the persistent workspace. It is reused if it matches the running
+/// firmware, otherwise or if `redump` is set the tables are dumped again. Edits
+/// that would be lost are moved aside first. An imported workspace is reused,
+/// or replaced by a dump of the running firmware if `redump` is set.
+pub fn open(redump: bool, progress: &dyn Fn(&str)) -> Result<Workspace> {
+    if let Some(source) = source() {
+        if !redump {
+            return reuse(progress);
+        }
+        progress(&format!(
+            "leaving the tables imported from {}",
+            source.display()
+        ));
+    }
+
+    if modified_dir().is_dir() {
+        if !redump && matches_firmware() {
+            return reuse(progress);
+        }
+        if !redump {
+            progress("firmware tables changed since the last dump");
+        }
+        move_aside(progress)?;
+    }
+
+    progress("dumping acpi tables");
+    let tables = init(progress)?;
+    report_overrides(progress);
+    start_versions(progress);
+    Ok(Workspace {
+        modified: modified()?,
+        tables,
+    })
+}
+
+/// Build the workspace from tables imported from `source`. A workspace
+/// already imported from the same source is reused unless `reimport` is set.
+pub fn import(source: &Path, reimport: bool, progress: &dyn Fn(&str)) -> Result<Workspace> {
+    let source = source
+        .canonicalize()
+        .with_context(|| format!("fail to open {}", source.display()))?;
+    if !reimport && modified_dir().is_dir() && self::source().as_ref() == Some(&source) {
+        return reuse(progress);
+    }
+    move_aside(progress)?;
+
+    progress(&format!("importing tables from {}", source.display()));
+    let tables = import_tables(&source)?;
+    start_versions(progress);
+    Ok(Workspace {
+        tables,
+        modified: vec![],
+    })
+}
+
+/// Dsl files in the workspace.
+pub fn tables() -> Result<Vec<String>> {
+    let mut files: Vec<String> = vec![];
+
+    let dir = modified_dir();
+    for entry in fs::read_dir(&dir).with_context(|| format!("fail to read {}", dir.display()))? {
+        let p = entry?.path();
+        let file_name = String::from(p.file_name().unwrap().to_str().unwrap());
+        files.push(file_name);
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Whether the dsl file differs from the firmware original.
+pub fn is_modified(dsl_file: &str) -> Result<bool> {
+    let mut modified = File::open(modified_dir().join(dsl_file))?;
+    let mut origin = match File::open(origin_dir().join(dsl_file)) {
+        Ok(origin) => origin,
+        Err(_) => return Ok(true),
+    };
+    Ok(!file_diff::diff_files(&mut modified, &mut origin))
+}
+
+/// Undo all edits to a dsl file. A table the firmware does not have is
+/// removed. Returns whether the file is still in the workspace.
+pub fn revert(dsl_file: &str) -> Result<bool> {
+    let origin = origin_dir().join(dsl_file);
+    let modified = modified_dir().join(dsl_file);
+    if !modified.exists() {
+        bail!("no table {}", dsl_file);
+    }
+    if !origin.exists() {
+        fs::remove_file(&modified)
+            .with_context(|| format!("fail to remove {}", modified.display()))?;
+        return Ok(false);
+    }
+    fs::copy(&origin, &modified)
+        .with_context(|| format!("fail to restore {}", modified.display()))?;
+    Ok(true)
+}
+
+/// Dsl files in the workspace that differ from the firmware original.
+pub fn modified() -> Result<Vec<String>> {
+    let mut modified = vec![];
+    for dsl_file in tables()? {
+        if is_modified(&dsl_file)? {
+            modified.push(dsl_file);
+        }
+    }
+    Ok(modified)
+}
+
+fn excluded_file() -> PathBuf {
+    root().join("excluded")
+}
+
+/// Dsl files left out of applies, one per line of the excluded file.
+pub fn excluded() -> Vec<String> {
+    fs::read_to_string(excluded_file())
+        .map(|text| {
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Leave `dsl_file` out of applies, or take it back in.
+pub fn set_excluded(dsl_file: &str, exclude: bool) -> Result<()> {
+    let mut excluded = excluded();
+    excluded.retain(|f| f != dsl_file);
+    if exclude {
+        excluded.push(dsl_file.to_string());
+        excluded.sort();
+    }
+    let path = excluded_file();
+    if excluded.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("fail to remove {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    let mut text = excluded.join("\n");
+    text.push('\n');
+    fs::write(&path, text).with_context(|| format!("fail to write {}", path.display()))
+}
+
+/// Modified dsl files an apply installs, the excluded ones left out.
+pub fn to_apply(modified: &[String]) -> Vec<String> {
+    let excluded = excluded();
+    modified
+        .iter()
+        .filter(|f| !excluded.contains(f))
+        .cloned()
+        .collect()
+}